@@ -1,23 +1,19 @@
-use std::{cell::Cell, ops::Add};
+use std::ops::Add;
 
 use na::{Point3, Vector3};
 
 use crate::{interval::Interval, ray::Ray};
 
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub struct Aabb {
-    pub x: Cell<Interval>,
-    pub y: Cell<Interval>,
-    pub z: Cell<Interval>,
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
 }
 
 impl Aabb {
     pub fn new() -> Aabb {
-        Aabb {
-            x: Cell::new(Interval::EMPTY),
-            y: Cell::new(Interval::EMPTY),
-            z: Cell::new(Interval::EMPTY),
-        }
+        Aabb::empty()
     }
 
     pub fn aabb(&mut self, a: &Point3<f64>, b: &Point3<f64>) {
@@ -25,38 +21,34 @@ impl Aabb {
         let y = Interval::new(a.y.min(b.y), a.y.max(b.y));
         let z = Interval::new(a.z.min(b.z), a.z.max(b.z));
 
-        self.pad_to_minimums();
+        self.x = x;
+        self.y = y;
+        self.z = z;
 
-        self.x.set(x);
-        self.y.set(y);
-        self.z.set(z);
+        self.pad_to_minimums();
     }
 
     pub fn aabb_from_boxes(box0: &Aabb, box1: &Aabb) -> Aabb {
-        let x = Interval::from_intervals(&box0.x.get(), &box1.x.get());
-        let y = Interval::from_intervals(&box0.y.get(), &box1.y.get());
-        let z = Interval::from_intervals(&box0.z.get(), &box1.z.get());
-
         Aabb {
-            x: Cell::new(x),
-            y: Cell::new(y),
-            z: Cell::new(z),
+            x: Interval::from_intervals(&box0.x, &box1.x),
+            y: Interval::from_intervals(&box0.y, &box1.y),
+            z: Interval::from_intervals(&box0.z, &box1.z),
         }
     }
 
     pub fn axis_interval(&self, n: usize) -> Interval {
         match n {
-            1 => self.y.get(),
-            2 => self.z.get(),
-            _ => self.x.get(),
+            1 => self.y,
+            2 => self.z,
+            _ => self.x,
         }
     }
 
     pub fn empty() -> Aabb {
         Aabb {
-            x: Cell::new(Interval::EMPTY),
-            y: Cell::new(Interval::EMPTY),
-            z: Cell::new(Interval::EMPTY),
+            x: Interval::EMPTY,
+            y: Interval::EMPTY,
+            z: Interval::EMPTY,
         }
     }
 
@@ -83,46 +75,64 @@ impl Aabb {
         true
     }
 
+    // Surface area of the box, zero for an empty one.
+    pub fn surface_area(&self) -> f64 {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        if dx < 0.0 || dy < 0.0 || dz < 0.0 {
+            return 0.0;
+        }
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    pub fn centroid(&self, axis: usize) -> f64 {
+        let interval = self.axis_interval(axis);
+        0.5 * (interval.min + interval.max)
+    }
+
     pub fn longest_axis(&self) -> usize {
-        if self.x.get().size() > self.y.get().size() {
-            if self.x.get().size() > self.z.get().size() {
+        if self.x.size() > self.y.size() {
+            if self.x.size() > self.z.size() {
                 0
             } else {
                 2
             }
+        } else if self.y.size() > self.z.size() {
+            1
         } else {
-            if self.y.get().size() > self.z.get().size() {
-                1
-            } else {
-                2
-            }
+            2
         }
     }
 
     // Adjust the AABB so that no side is narrower than some delta, padding if necessary.
-    fn pad_to_minimums(&self) {
+    fn pad_to_minimums(&mut self) {
         let delta = 0.0001;
 
-        if self.x.get().size() < delta {
-            self.x.set(self.x.get().expand(delta));
+        if self.x.size() < delta {
+            self.x = self.x.expand(delta);
         }
-        if self.y.get().size() < delta {
-            self.y.set(self.y.get().expand(delta));
+        if self.y.size() < delta {
+            self.y = self.y.expand(delta);
         }
-        if self.z.get().size() < delta {
-            self.z.set(self.z.get().expand(delta));
+        if self.z.size() < delta {
+            self.z = self.z.expand(delta);
         }
     }
 }
 
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb::empty()
+    }
+}
+
 impl Add<&Vector3<f64>> for &Aabb {
     type Output = Aabb;
 
     fn add(self, offset: &Vector3<f64>) -> Aabb {
         Aabb {
-            x: Cell::new(self.x.get() + offset.x),
-            y: Cell::new(self.y.get() + offset.y),
-            z: Cell::new(self.z.get() + offset.z),
+            x: self.x + offset.x,
+            y: self.y + offset.y,
+            z: self.z + offset.z,
         }
     }
 }
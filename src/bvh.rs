@@ -3,82 +3,470 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::ray::Ray;
-use std::rc::Rc;
+use na::Point3;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
 
-// Bounding Volume Hierarchy
+// Relative costs of a bounding box test and of an object intersection, for the SAH.
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+
+// Number of buckets candidate SAH splits are evaluated on, per axis.
+const SAH_BINS: usize = 16;
+
+// Nodes deeper than this are split at the median, whatever the split method, so that
+// traversal never needs more than STACK_SIZE pending nodes.
+const MAX_SAH_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+
+// Objects per leaf of a scene hierarchy.
+const MAX_LEAF_OBJECTS: usize = 2;
+
+// How the objects of a node are divided between its two children.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitMethod {
+    // Cut at the middle object along the longest axis.
+    Median,
+    // Cut where the surface area heuristic predicts the cheapest traversal.
+    Sah,
+}
+
+// Shape and expected traversal cost of a hierarchy, to compare split methods.
+#[derive(Clone, Copy, Debug)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub leaves: usize, // nodes whose children are objects
+    pub max_depth: usize,
+    pub object_tests: usize, // object references in leaves
+    // Expected cost of a ray that hits the root box, in units of one box test.
+    pub sah_cost: f64,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves, depth {}, {} object references, SAH cost {:.2}",
+            self.nodes, self.leaves, self.max_depth, self.object_tests, self.sah_cost
+        )
+    }
+}
+
+// Bounding Volume Hierarchy over the objects of a scene.
 pub struct BVHNode {
-    left: Rc<dyn Hittable>,
-    right: Rc<dyn Hittable>,
-    bbox: Aabb,
+    bvh: FlatBvh,
+    objects: Vec<Arc<dyn Hittable>>, // ordered so that every leaf covers a contiguous range
 }
 
 impl BVHNode {
     pub fn new_from_list(list: &HittableList) -> BVHNode {
-        BVHNode::new(list.objects.clone(), 0, list.objects.len())
+        BVHNode::new_from_list_with(list, SplitMethod::Median)
     }
 
-    fn new(mut objects: Vec<Rc<dyn Hittable>>, start: usize, end: usize) -> BVHNode {
+    pub fn new_from_list_with(list: &HittableList, method: SplitMethod) -> BVHNode {
+        let boxes: Vec<Aabb> = list.objects.iter().map(|o| *o.bounding_box()).collect();
+        let (bvh, order) = FlatBvh::build(&boxes, method, MAX_LEAF_OBJECTS);
+        let objects = order
+            .iter()
+            .map(|&i| list.objects[i as usize].clone())
+            .collect();
+        BVHNode { bvh, objects }
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        self.bvh.stats
+    }
+}
+
+impl Hittable for BVHNode {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        self.bvh.hit(r, ray_t, |leaf, interval| {
+            let mut closest = None;
+            for object in &self.objects[leaf] {
+                let t_max = closest.unwrap_or(interval.max);
+                if object.hit(r, Interval::new(interval.min, t_max), rec) {
+                    closest = Some(rec.t);
+                }
+            }
+            closest
+        })
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+// Hierarchy stored as a flat array of nodes in depth-first order, over primitives known
+// only by their bounding boxes. Shared by the scene BVH and the triangle meshes.
+pub(crate) struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    stats: BvhStats,
+}
+
+#[derive(Clone, Copy)]
+struct FlatNode {
+    bbox: Aabb,
+    // Leaves (count > 0) cover order[offset..offset + count]. Interior nodes store their
+    // left child right after themselves and their right child at `offset`.
+    offset: u32,
+    count: u16,
+    axis: u8, // split axis, to visit the child nearer to the ray origin first
+}
+
+impl FlatBvh {
+    // Builds the hierarchy over `boxes`. Leaves refer to ranges of the returned order,
+    // which lists every primitive index exactly once.
+    pub(crate) fn build(
+        boxes: &[Aabb],
+        method: SplitMethod,
+        max_leaf: usize,
+    ) -> (FlatBvh, Vec<u32>) {
+        let mut builder = Builder {
+            boxes,
+            centroids: boxes
+                .iter()
+                .map(|b| Point3::new(b.centroid(0), b.centroid(1), b.centroid(2)))
+                .collect(),
+            nodes: Vec::with_capacity(2 * boxes.len() / max_leaf + 1),
+            method,
+            max_leaf,
+        };
+
+        let mut order: Vec<u32> = (0..boxes.len() as u32).collect();
+        let stats = if boxes.is_empty() {
+            BvhStats {
+                nodes: 0,
+                leaves: 0,
+                max_depth: 0,
+                object_tests: 0,
+                sah_cost: 0.0,
+            }
+        } else {
+            builder.node(&mut order, 0, 0).1
+        };
+        assert!(stats.max_depth <= STACK_SIZE, "BVH too deep to traverse");
+
+        let bvh = FlatBvh {
+            nodes: builder.nodes,
+            stats,
+        };
+        (bvh, order)
+    }
+
+    // Walks the nodes hit by the ray, nearer children first. `hit_leaf` is given the range
+    // of the order covered by a leaf and the current interval, and returns the distance of
+    // the closest hit it found in it, if any.
+    pub(crate) fn hit(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        mut hit_leaf: impl FnMut(Range<usize>, Interval) -> Option<f64>,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let dir_is_neg = [
+            r.direction().x < 0.0,
+            r.direction().y < 0.0,
+            r.direction().z < 0.0,
+        ];
+        let mut hit_anything = false;
+        let mut closest_so_far = ray_t.max;
+
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            let interval = Interval::new(ray_t.min, closest_so_far);
+            if node.bbox.hit(r, interval) {
+                if node.count == 0 {
+                    // Go down the nearer child and come back for the other one.
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    index = near;
+                    continue;
+                }
+
+                let start = node.offset as usize;
+                if let Some(t) = hit_leaf(start..start + node.count as usize, interval) {
+                    hit_anything = true;
+                    closest_so_far = t;
+                }
+            }
+
+            if stack_len == 0 {
+                return hit_anything;
+            }
+            stack_len -= 1;
+            index = stack[stack_len];
+        }
+    }
+
+    pub(crate) fn bounding_box(&self) -> &Aabb {
+        static EMPTY: Aabb = Aabb {
+            x: Interval::EMPTY,
+            y: Interval::EMPTY,
+            z: Interval::EMPTY,
+        };
+        self.nodes.first().map_or(&EMPTY, |root| &root.bbox)
+    }
+}
+
+struct Builder<'a> {
+    boxes: &'a [Aabb],
+    centroids: Vec<Point3<f64>>,
+    nodes: Vec<FlatNode>,
+    method: SplitMethod,
+    max_leaf: usize,
+}
+
+impl Builder<'_> {
+    // Appends the subtree over `ids`, which start at `offset` in the final order, and
+    // returns its index and statistics. The ids are partitioned in place, so the two
+    // children always cover disjoint halves of the slice.
+    fn node(&mut self, ids: &mut [u32], offset: usize, depth: usize) -> (usize, BvhStats) {
         let mut bbox = Aabb::empty();
+        let mut centroid_min = Point3::from([f64::INFINITY; 3]);
+        let mut centroid_max = Point3::from([f64::NEG_INFINITY; 3]);
+        for &id in ids.iter() {
+            bbox = Aabb::aabb_from_boxes(&bbox, &self.boxes[id as usize]);
+            let c = &self.centroids[id as usize];
+            centroid_min = centroid_min.inf(c);
+            centroid_max = centroid_max.sup(c);
+        }
 
-        for i in start..end {
-            bbox = Aabb::aabb_from_boxes(&bbox, objects[i].bounding_box());
+        let index = self.nodes.len();
+        if ids.len() <= self.max_leaf {
+            self.nodes.push(FlatNode {
+                bbox,
+                offset: offset as u32,
+                count: ids.len() as u16,
+                axis: 0,
+            });
+            let stats = BvhStats {
+                nodes: 1,
+                leaves: 1,
+                max_depth: 1,
+                object_tests: ids.len(),
+                sah_cost: TRAVERSAL_COST + ids.len() as f64 * INTERSECTION_COST,
+            };
+            return (index, stats);
         }
 
-        let axis = bbox.longest_axis();
+        let extent = centroid_max - centroid_min;
+        let split = if self.method == SplitMethod::Sah && depth < MAX_SAH_DEPTH {
+            self.sah_partition(ids, &centroid_min, &centroid_max)
+        } else {
+            None
+        };
 
-        let comparator = |a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>| {
-            let box_a = a.bounding_box();
-            let box_b = b.bounding_box();
-            box_a
-                .axis_interval(axis)
-                .min
-                .partial_cmp(&box_b.axis_interval(axis).min)
-                .unwrap()
+        let (axis, mid) = split.unwrap_or_else(|| {
+            let mid = ids.len() / 2;
+            if extent.max() <= 0.0 {
+                // Every centroid is at the same place: any split is as good as another.
+                return (bbox.longest_axis(), mid);
+            }
+
+            let axis = extent.imax();
+            let centroids = &self.centroids;
+            ids.select_nth_unstable_by(mid, |&a, &b| {
+                centroids[a as usize][axis].total_cmp(&centroids[b as usize][axis])
+            });
+            (axis, mid)
+        });
+
+        self.nodes.push(FlatNode {
+            bbox,
+            offset: 0,
+            count: 0,
+            axis: axis as u8,
+        });
+        let (left_ids, right_ids) = ids.split_at_mut(mid);
+        let (left, left_stats) = self.node(left_ids, offset, depth + 1);
+        let (right, right_stats) = self.node(right_ids, offset + mid, depth + 1);
+        self.nodes[index].offset = right as u32;
+
+        let stats = combine_stats(
+            &bbox,
+            (&self.nodes[left].bbox, left_stats),
+            (&self.nodes[right].bbox, right_stats),
+        );
+        (index, stats)
+    }
+
+    // Partitions the ids so that the cheapest split under the surface area heuristic
+    // separates ids[..mid] from ids[mid..], and returns the split axis and mid. Candidate
+    // splits are the boundaries of SAH_BINS equal buckets of the centroid bounds, on
+    // every axis. Returns None when all the centroids coincide.
+    fn sah_partition(
+        &self,
+        ids: &mut [u32],
+        centroid_min: &Point3<f64>,
+        centroid_max: &Point3<f64>,
+    ) -> Option<(usize, usize)> {
+        let bin_of = |id: u32, axis: usize| {
+            let extent = centroid_max[axis] - centroid_min[axis];
+            let offset = (self.centroids[id as usize][axis] - centroid_min[axis]) / extent;
+            ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
         };
 
-        let left;
-        let right;
+        let mut best: Option<(f64, usize, usize)> = None; // (cost, axis, first bin on the right)
+        for axis in 0..3 {
+            if centroid_max[axis] - centroid_min[axis] <= 0.0 {
+                continue;
+            }
 
-        let object_span = end - start;
-        match object_span {
-            1 => {
-                left = objects[start].clone();
-                right = objects[start].clone();
+            let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+            for &id in ids.iter() {
+                let bin = &mut bins[bin_of(id, axis)];
+                bin.0 = Aabb::aabb_from_boxes(&bin.0, &self.boxes[id as usize]);
+                bin.1 += 1;
             }
-            2 => {
-                left = objects[start].clone();
-                right = objects[start + 1].clone();
+
+            // Sweep from the right to get the area and count of every right-hand side.
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let (mut bbox, mut count) = (Aabb::empty(), 0);
+            for split in (1..SAH_BINS).rev() {
+                bbox = Aabb::aabb_from_boxes(&bbox, &bins[split].0);
+                count += bins[split].1;
+                right_area[split] = bbox.surface_area();
+                right_count[split] = count;
             }
-            _ => {
-                objects[start..end].sort_by(comparator);
-                let mid = start + object_span / 2;
 
-                left = Rc::new(BVHNode::new(objects.clone(), start, mid + 1));
-                right = Rc::new(BVHNode::new(objects.clone(), mid, end));
+            let (mut bbox, mut count) = (Aabb::empty(), 0);
+            for split in 1..SAH_BINS {
+                bbox = Aabb::aabb_from_boxes(&bbox, &bins[split - 1].0);
+                count += bins[split - 1].1;
+                if count == 0 || right_count[split] == 0 {
+                    continue;
+                }
+
+                let cost = bbox.surface_area() * count as f64
+                    + right_area[split] * right_count[split] as f64;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
             }
-        };
+        }
 
-        BVHNode { left, right, bbox }
+        let (_, axis, split) = best?;
+
+        let mut mid = 0;
+        for i in 0..ids.len() {
+            if bin_of(ids[i], axis) < split {
+                ids.swap(i, mid);
+                mid += 1;
+            }
+        }
+        Some((axis, mid))
     }
 }
 
-impl Hittable for BVHNode {
-    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        if !self.bbox.hit(&r, ray_t) {
-            return false;
+fn combine_stats(bbox: &Aabb, left: (&Aabb, BvhStats), right: (&Aabb, BvhStats)) -> BvhStats {
+    let area = bbox.surface_area();
+    let ((left_box, l), (right_box, r)) = (left, right);
+
+    // Children are reached with a probability proportional to their surface area.
+    let child_cost = if area > 0.0 {
+        (left_box.surface_area() * l.sah_cost + right_box.surface_area() * r.sah_cost) / area
+    } else {
+        l.sah_cost + r.sah_cost
+    };
+
+    BvhStats {
+        nodes: 1 + l.nodes + r.nodes,
+        leaves: l.leaves + r.leaves,
+        max_depth: 1 + l.max_depth.max(r.max_depth),
+        object_tests: l.object_tests + r.object_tests,
+        sah_cost: TRAVERSAL_COST + child_cost,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::DefaultMaterial, sphere::Sphere, triangle::Triangle};
+    use na::Vector3;
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    fn point(rng: &mut SmallRng, size: f64) -> Point3<f64> {
+        Point3::new(
+            rng.gen_range(-size..size),
+            rng.gen_range(-size..size),
+            rng.gen_range(-size..size),
+        )
+    }
+
+    // Spheres and triangles of many sizes, overlapping each other.
+    fn scene(rng: &mut SmallRng) -> HittableList {
+        let mat = Arc::new(DefaultMaterial::new());
+        let mut list = HittableList::new();
+        for _ in 0..200 {
+            let center = point(rng, 10.0);
+            list.add(Arc::new(Sphere::new(
+                center,
+                rng.gen_range(0.05..1.5),
+                mat.clone(),
+            )));
+            let a = point(rng, 10.0);
+            let b = a + point(rng, 2.0).coords;
+            let c = a + point(rng, 2.0).coords;
+            list.add(Arc::new(Triangle::new(a, b, c, mat.clone())));
         }
+        list
+    }
 
-        let hit_left = self.left.hit(&r, ray_t, rec);
-        let hit_right = self.right.hit(
-            &r,
-            Interval::new(ray_t.min, if hit_left { rec.t } else { ray_t.max }),
-            rec,
-        );
+    fn closest(object: &dyn Hittable, r: &Ray) -> Option<(f64, Point3<f64>, Vector3<f64>)> {
+        let mut rec = HitRecord::default();
+        object
+            .hit(r, Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some((rec.t, rec.p, rec.normal))
+    }
+
+    #[test]
+    fn hits_match_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let list = scene(&mut rng);
+        let bvhs = [
+            BVHNode::new_from_list_with(&list, SplitMethod::Median),
+            BVHNode::new_from_list_with(&list, SplitMethod::Sah),
+        ];
 
-        hit_left || hit_right
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = point(&mut rng, 15.0);
+            let r = Ray::new(origin, point(&mut rng, 10.0) - origin);
+            let expected = closest(&list, &r);
+            hits += expected.is_some() as usize;
+            for bvh in &bvhs {
+                assert_eq!(closest(bvh, &r), expected);
+            }
+        }
+        assert!(hits > 400, "only {} rays hit", hits);
     }
 
-    fn bounding_box(&self) -> &Aabb {
-        &self.bbox
+    #[test]
+    fn bounding_box_contains_every_object() {
+        let mut rng = SmallRng::seed_from_u64(8);
+        let list = scene(&mut rng);
+        let bvh = BVHNode::new_from_list_with(&list, SplitMethod::Sah);
+        for object in &list.objects {
+            let inner = object.bounding_box();
+            let outer = bvh.bounding_box();
+            for axis in 0..3 {
+                assert!(outer.axis_interval(axis).min <= inner.axis_interval(axis).min);
+                assert!(outer.axis_interval(axis).max >= inner.axis_interval(axis).max);
+            }
+        }
     }
 }
@@ -1,15 +1,20 @@
-use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
 use na::{Point3, Vector3};
 
 use crate::{
-    color::{write_color, Color},
+    color::{luminance, Color},
     degrees_to_radians,
+    framebuffer::Framebuffer,
     hittable::{HitRecord, Hittable},
+    hittable_list::HittableList,
     interval::Interval,
-    random_double,
     ray::Ray,
-    vec3::random_in_unit_disk,
+    sampler::{Sampler, SamplerKind},
+    seed_rng,
+    vec3::sample_unit_disk,
 };
 
 pub struct Camera {
@@ -22,8 +27,10 @@ pub struct Camera {
     pixel_delta_vertical: Vector3<f64>,
     pixel00_loc: Point3<f64>,
     pub samples_per_pixel: usize,
-    pixel_samples_scale: f64,
     pub max_depth: usize,
+    // Bounces after which Russian roulette may end dim paths; set it to max_depth or more
+    // to trace every path up to max_depth.
+    pub roulette_depth: usize,
     pub focal_length: f64,
 
     pub vfov: f64, // vertical field-of-view
@@ -31,6 +38,10 @@ pub struct Camera {
     pub lookat: Point3<f64>,
     pub vup: Vector3<f64>,
 
+    // Rays are spread over this time interval, blurring objects that move during it.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+
     pub defocus_angle: f64, // Variation angle of rays through each pixel
     pub focus_dist: f64,    // Distance from the camera to the focus plane
     defocus_disk_horizontal: Vector3<f64>,
@@ -42,8 +53,28 @@ pub struct Camera {
 
     pub background: Color,
     pub brightness: f64,
+
+    // Adaptive sampling: with a threshold above 0, pixels stop being sampled once their
+    // error drops below it, and the samples they don't use go to the noisier ones.
+    // samples_per_pixel is then the average over the image.
+    pub adaptive_threshold: f64,
+    pub min_samples_per_pixel: usize, // Samples of every pixel before its error is trusted
+    sample_counts: Vec<usize>,
+
+    // Where the random numbers of each sample come from, white noise by default.
+    pub sampler: SamplerKind,
+    sampler_seed: u64,
+
+    pub threads: usize, // Number of render threads, 0 uses every available core
+    pub seed: Option<u64>, // Makes renders reproducible, whatever the thread count
+    pub progress: Option<ProgressCallback>,
 }
 
+// Called with (rows_done, total_rows) every time a scanline is finished. With adaptive
+// sampling, pixels are traced in several passes and it is called with the number of
+// samples traced and the total budget instead.
+pub type ProgressCallback = Box<dyn Fn(usize, usize) + Send + Sync>;
+
 impl Camera {
     pub fn new(aspect_ratio: f64, img_width: usize) -> Camera {
         Camera {
@@ -55,14 +86,17 @@ impl Camera {
             pixel_delta_vertical: Vector3::new(0.0, 0.0, 0.0),
             pixel00_loc: Point3::new(0.0, 0.0, 0.0),
             samples_per_pixel: 10,
-            pixel_samples_scale: 1.0,
             max_depth: 10,
+            roulette_depth: 3,
             focal_length: 1.0,
             vfov: 90.0,
             lookfrom: Point3::new(0.0, 0.0, 0.0),
             lookat: Point3::new(0.0, 0.0, -1.0),
             vup: Vector3::new(0.0, 1.0, 0.0),
 
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+
             defocus_angle: 0.0,
             focus_dist: 10.0,
             defocus_disk_horizontal: Vector3::new(0.0, 0.0, 0.0),
@@ -74,40 +108,166 @@ impl Camera {
 
             background: Color::new(0.0, 0.0, 0.0),
             brightness: 1.0,
+
+            adaptive_threshold: 0.0,
+            min_samples_per_pixel: 16,
+            sample_counts: Vec::new(),
+
+            sampler: SamplerKind::Independent,
+            sampler_seed: 0,
+
+            threads: 0,
+            seed: None,
+            progress: None,
         }
     }
 
-    pub fn render(&mut self, world: &dyn Hittable) {
+    // `lights` lists the emissive spheres and quads (or transforms of them) to sample
+    // directly, which removes most of the noise of small lights. It can be empty, then
+    // light is only found by rays that happen to hit an emitter.
+    pub fn render(&mut self, world: &dyn Hittable, lights: &HittableList) -> Framebuffer {
         self.initialize();
+        self.sampler_seed = self.seed.unwrap_or_else(rand::random);
+
+        let cam = &*self;
+        let mut stats = vec![PixelStats::new(); cam.img_width * cam.img_height];
+
+        if cam.adaptive_threshold > 0.0 {
+            let budget = cam.samples_per_pixel * stats.len();
+            let samples_done = AtomicUsize::new(0);
+            let report = |samples: usize| {
+                let done = samples_done.fetch_add(samples, Ordering::Relaxed) + samples;
+                if let Some(progress) = &cam.progress {
+                    progress(done, budget);
+                }
+            };
+
+            // Every pixel gets the same first samples. Then, pass after pass, the pixels
+            // still above the threshold double their samples, as far as the budget goes.
+            let mut samples = cam.min_samples_per_pixel.clamp(1, cam.samples_per_pixel);
+            cam.trace_pass(&mut stats, 0, samples, world, lights, &report);
+            let mut remaining = budget - samples * stats.len();
+
+            for pass in 1.. {
+                let mut active = 0;
+                for pixel in stats.iter_mut().filter(|pixel| !pixel.converged) {
+                    if pixel.error() < cam.adaptive_threshold {
+                        pixel.converged = true;
+                    } else {
+                        active += 1;
+                    }
+                }
+                if active == 0 {
+                    break;
+                }
 
-        // Render
-        print!("P3\n{} {}\n255\n", self.img_width, self.img_height);
-
-        for j in 0..self.img_height {
-            eprint!("\rScanlines remaining: {} \r", self.img_height - j);
-            std::io::stderr().flush().unwrap();
-
-            for i in 0..self.img_width {
-                let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                for _ in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i as f64, j as f64);
-                    pixel_color += self.ray_color(&r, self.max_depth, world);
+                let batch = samples.min(remaining / active);
+                if batch == 0 {
+                    break;
                 }
-                pixel_color *= self.pixel_samples_scale * self.brightness;
-                write_color(&pixel_color);
+                cam.trace_pass(&mut stats, pass, batch, world, lights, &report);
+                remaining -= batch * active;
+                samples += batch;
             }
+        } else {
+            let rows_done = AtomicUsize::new(0);
+            let report = |_| {
+                let done = rows_done.fetch_add(1, Ordering::Relaxed) + 1;
+                if let Some(progress) = &cam.progress {
+                    progress(done, cam.img_height);
+                }
+            };
+            cam.trace_pass(&mut stats, 0, cam.samples_per_pixel, world, lights, &report);
         }
 
-        eprintln!("\rDone.                           \n");
-        std::io::stderr().flush().unwrap();
+        let mut image = Framebuffer::new(cam.img_width, cam.img_height);
+        for (pixel, stats) in image.pixels.iter_mut().zip(&stats) {
+            *pixel = stats.sum * cam.brightness / stats.count as f64;
+        }
+        self.sample_counts = stats.iter().map(|stats| stats.count).collect();
+        image
+    }
+
+    // Number of samples traced for each pixel by the last render, row by row from the
+    // top-left corner.
+    pub fn sample_counts(&self) -> &[usize] {
+        &self.sample_counts
+    }
+
+    // Debug view of `sample_counts`, from black for no samples to white for the pixels
+    // that got the most.
+    pub fn sample_count_image(&self) -> Framebuffer {
+        let mut image = Framebuffer::new(self.img_width, self.img_height);
+        let max = self.sample_counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+        for (pixel, &count) in image.pixels.iter_mut().zip(&self.sample_counts) {
+            *pixel = Color::repeat(count as f64 / max);
+        }
+        image
+    }
+
+    // Adds `samples` samples to every pixel that hasn't converged. `report` is called
+    // with the number of samples traced after each scanline.
+    fn trace_pass(
+        &self,
+        stats: &mut [PixelStats],
+        pass: usize,
+        samples: usize,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        report: &(dyn Fn(usize) + Sync),
+    ) {
+        // Workers pull scanlines one at a time so that expensive rows don't stall the others.
+        let rows = Mutex::new(stats.chunks_mut(self.img_width).enumerate());
+
+        thread::scope(|scope| {
+            for _ in 0..self.thread_count() {
+                scope.spawn(|| {
+                    let mut sampler = self
+                        .sampler
+                        .create(self.samples_per_pixel, self.sampler_seed);
+                    loop {
+                        let Some((j, row)) = rows.lock().unwrap().next() else {
+                            break;
+                        };
+
+                        if let Some(seed) = self.seed {
+                            seed_rng(
+                                seed ^ (j as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+                                    ^ (pass as u64).wrapping_mul(0xbf58_476d_1ce4_e5b9),
+                            );
+                        }
+
+                        let mut traced = 0;
+                        for (i, pixel) in row.iter_mut().enumerate() {
+                            if pixel.converged {
+                                continue;
+                            }
+                            for index in pixel.count..pixel.count + samples {
+                                sampler.start_pixel_sample(i, j, index);
+                                let r = self.get_ray(i as f64, j as f64, &mut *sampler);
+                                let color = self.ray_color(r, world, lights, &mut *sampler);
+                                pixel.add(color, self.brightness);
+                            }
+                            traced += samples;
+                        }
+                        report(traced);
+                    }
+                });
+            }
+        });
+    }
+
+    fn thread_count(&self) -> usize {
+        if self.threads > 0 {
+            return self.threads;
+        }
+        thread::available_parallelism().map_or(1, |n| n.get())
     }
 
     pub fn initialize(&mut self) {
         // Calculate the image height and ensure that it's at least 1
         self.img_height = ((self.img_width as f64 / self.aspect_ratio) as usize).max(1);
 
-        self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
-
         // Camera (eye point)
         self.camera_center = self.lookfrom;
 
@@ -146,8 +306,8 @@ impl Camera {
 
     // Construct a camera ray originating from the defocus disk and directed at a randomly
     // sampled point around the pixel location i, j.
-    fn get_ray(&self, i: f64, j: f64) -> Ray {
-        let offset = Self::sample_square();
+    fn get_ray(&self, i: f64, j: f64, sampler: &mut dyn Sampler) -> Ray {
+        let offset = Self::sample_square(sampler);
         let pixel_sample = self.pixel00_loc
             + ((i + offset.x) * self.pixel_delta_horizontal)
             + ((j + offset.y) * self.pixel_delta_vertical);
@@ -155,56 +315,240 @@ impl Camera {
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.camera_center
         } else {
-            self.defocus_disk_sample()
+            self.defocus_disk_sample(sampler)
         };
 
         let ray_direction = pixel_sample - ray_origin;
+        let ray_time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + sampler.get_1d() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
 
-        Ray::new(ray_origin, ray_direction)
+        Ray::new_with_time(ray_origin, ray_direction, ray_time).with_sample(sampler.get_1d())
     }
 
-    fn defocus_disk_sample(&self) -> Point3<f64> {
-        let p = random_in_unit_disk();
+    // Next event estimation: light reaching the hit point straight from a random point on
+    // one of the lights, through a shadow ray. Whatever the shadow ray hits first gives the
+    // emitted light, so occluders block it and other lights in the way are counted.
+    fn sample_lights(
+        &self,
+        wo: &Vector3<f64>,
+        rec: &HitRecord,
+        time: f64,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let direction = lights.random(&rec.p, time, sampler);
+        let shadow_ray = Ray::new_with_time(rec.p, direction, time).with_sample(sampler.get_1d());
+
+        let wi = direction.normalize();
+        let f = rec.mat.eval(wo, &wi, rec);
+        let light_pdf = lights.pdf_value(&shadow_ray);
+        if light_pdf <= 0.0 || f == Color::zeros() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let mut hit = HitRecord::default();
+        if !world.hit(&shadow_ray, Interval::new(0.001, f64::INFINITY), &mut hit) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let emitted = hit.mat.emitted(hit.u, hit.v, &hit.p);
+        let weight = power_heuristic(light_pdf, rec.mat.pdf(wo, &wi, rec));
+        f.component_mul(&emitted) * weight / light_pdf
+    }
+
+    fn defocus_disk_sample(&self, sampler: &mut dyn Sampler) -> Point3<f64> {
+        let p = sample_unit_disk(sampler.get_2d());
         self.camera_center + p.x * self.defocus_disk_horizontal + p.y * self.defocus_disk_vertical
     }
 
-    fn sample_square() -> Vector3<f64> {
-        Vector3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
+    fn sample_square(sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let (x, y) = sampler.get_2d();
+        Vector3::new(x - 0.5, y - 0.5, 0.0)
     }
 
-    fn ray_color(&mut self, r: &Ray, depth: usize, world: &dyn Hittable) -> Color {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
+    // Follows the path of a camera ray through up to `max_depth` hits. `throughput` is the
+    // fraction of the light found at the current hit that reaches the camera, and
+    // `emission_weight` scales the light emitted by the surface the ray hits, 0 when it
+    // was already counted by sampling the lights.
+    fn ray_color(
+        &self,
+        mut ray: Ray,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut emission_weight = 1.0;
+
+        for depth in 1..=self.max_depth {
+            let mut rec = HitRecord::default();
+
+            if !world.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                color += throughput.component_mul(&self.background);
+                break;
+            }
+
+            let emitted = rec.mat.emitted(rec.u, rec.v, &rec.p);
+            color += emission_weight * throughput.component_mul(&emitted);
+
+            let wo = -ray.direction().normalize();
+            let Some(sample) = rec.mat.sample(&wo, &rec, sampler) else {
+                break;
+            };
+            if sample.pdf <= 0.0 {
+                break;
+            }
+            let scattered =
+                Ray::new_with_time(rec.p, sample.wi, ray.time()).with_sample(sampler.get_1d());
+
+            // Light reaching a surface from a light is estimated twice, by the shadow ray
+            // and by the scattered ray if it hits the light, and the two estimates are
+            // blended with multiple importance sampling. Shadow rays never fall in a delta
+            // lobe, so light found through one only comes from the scattered ray.
+            emission_weight = 1.0;
+            if !lights.objects.is_empty() {
+                let direct = self.sample_lights(&wo, &rec, ray.time(), world, lights, sampler);
+                color += throughput.component_mul(&direct);
+                if !sample.delta {
+                    emission_weight = power_heuristic(sample.pdf, lights.pdf_value(&scattered));
+                }
+            }
+
+            throughput = throughput.component_mul(&(sample.f / sample.pdf));
+            ray = scattered;
+
+            // Russian roulette: past `roulette_depth`, a path carrying little light goes on
+            // with a probability equal to its throughput, and the survivors are brightened
+            // by the same factor so that the average is unchanged.
+            if depth >= self.roulette_depth {
+                let survival = throughput.max().min(1.0);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
         }
 
-        let mut rec = HitRecord::default();
+        color
+    }
+}
+
+// Running sum of the samples of a pixel, with the mean and variance of their luminance.
+#[derive(Clone)]
+struct PixelStats {
+    sum: Color,
+    count: usize,
+    mean: f64,
+    m2: f64, // Sum of squared differences to the mean
+    converged: bool,
+}
 
-        if !world.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
-            return self.background;
+impl PixelStats {
+    fn new() -> PixelStats {
+        PixelStats {
+            sum: Color::new(0.0, 0.0, 0.0),
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            converged: false,
         }
+    }
 
-        let color_from_emission = rec.mat.emitted(rec.u, rec.v, &rec.p);
+    // Welford's online update; `brightness` scales the luminance the same way as the
+    // final image.
+    fn add(&mut self, color: Color, brightness: f64) {
+        self.sum += color;
+        self.count += 1;
+        let value = luminance(&color) * brightness;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
 
-        let mut scattered = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
-        let mut attenuation = Color::new(0.0, 0.0, 0.0);
-        if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-            let color_from_scatter =
-                attenuation.component_mul(&self.ray_color(&scattered, depth - 1, world));
-            return color_from_emission + color_from_scatter;
+    // Half width of the 95% confidence interval of the mean luminance, divided by its
+    // square root. Noise is measured as it shows once the image is gamma corrected, so
+    // dark pixels don't need as many samples to reach the same relative error.
+    //
+    // Samples that all agree don't prove the pixel is smooth: a light reached by few
+    // paths may just not have been found yet, and stopping there would leave the pixel
+    // too dark. The variance is never taken below that of samples which would find a
+    // white path once in count + 1 tries.
+    fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
         }
-        return color_from_emission;
-
-        // if world.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
-        //     let mut scattered = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
-        //     let mut attenuation = Color::new(0.0, 0.0, 0.0);
-        //     if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-        //         return attenuation.component_mul(&self.ray_color(&scattered, depth - 1, world));
-        //     }
-        //     return Color::new(0.0, 0.0, 0.0);
-        // }
-
-        // let unit_direction = r.direction().normalize();
-        // let a = 0.5 * (unit_direction.y + 1.0);
-        // (1.0 - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0)
+        let p = 1.0 / (self.count + 1) as f64;
+        let variance = (self.m2 / (self.count - 1) as f64).max(p * (1.0 - p));
+        1.96 * (variance / self.count as f64).sqrt() / self.mean.max(1e-4).sqrt()
+    }
+}
+
+// Multiple importance sampling weight of a sample drawn with density `pdf`, when another
+// strategy could have drawn it with density `other_pdf`. The weights of both strategies
+// add up to 1.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a.is_infinite() {
+        return 1.0;
+    }
+    a / (a + b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{DiffuseLight, Lambertian},
+        quad::Quad,
+        sphere::Sphere,
+    };
+    use std::sync::Arc;
+
+    // A grey floor lit only by a small, bright sphere that few paths find by chance.
+    fn render(adaptive_threshold: f64) -> (f64, usize) {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Quad::new(
+            Point3::new(-10.0, 0.0, -10.0),
+            Vector3::new(20.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 20.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(1.6, 0.5, 0.0),
+            0.3,
+            Arc::new(DiffuseLight::new(Color::new(20.0, 20.0, 20.0))),
+        )));
+
+        let mut cam = Camera::new(1.0, 12);
+        cam.samples_per_pixel = 256;
+        cam.max_depth = 4;
+        cam.lookfrom = Point3::new(0.0, 4.0, 0.1);
+        cam.lookat = Point3::new(0.0, 0.0, 0.0);
+        cam.vfov = 30.0;
+        cam.adaptive_threshold = adaptive_threshold;
+        cam.min_samples_per_pixel = 4;
+        cam.threads = 1;
+        cam.seed = Some(2);
+
+        let image = cam.render(&world, &HittableList::new());
+        let mean = image.pixels.iter().map(luminance).sum::<f64>() / image.pixels.len() as f64;
+        (mean, cam.sample_counts().iter().sum())
+    }
+
+    #[test]
+    fn adaptive_sampling_keeps_the_mean() {
+        let (fixed, fixed_samples) = render(0.0);
+        let (adaptive, adaptive_samples) = render(0.05);
+        assert!(adaptive_samples <= fixed_samples);
+        assert!(
+            (adaptive / fixed - 1.0).abs() < 0.1,
+            "adaptive mean {} against {} with fixed samples",
+            adaptive,
+            fixed
+        );
     }
 }
@@ -0,0 +1,337 @@
+use std::path::PathBuf;
+
+use rt::{
+    bvh::SplitMethod,
+    color::Transfer,
+    output::{BitDepth, ImageFormat},
+    sampler::SamplerKind,
+    Point3,
+};
+
+pub const USAGE: &str = "\
+Usage: rt [OPTIONS]
+
+Renders a scene and writes the image to a file, or as PPM to stdout.
+
+Scene:
+      --scene <NAME>        custom, first, second, third or fourth [default: custom]
+  -f, --first               Same as --scene first
+  -s, --second              Same as --scene second
+  -t, --third               Same as --scene third
+  -fo, --fourth             Same as --scene fourth
+      --scene-file <PATH>   Load the camera and objects from a scene file instead
+
+Camera:
+      --width <PIXELS>      Image width [default: 800]
+      --aspect <RATIO>      Aspect ratio, as 4:3, 4/3 or 1.333 [default: 4:3]
+      --spp <N>             Samples per pixel, on average with --adaptive [default: 1000]
+      --depth <N>           Maximum number of bounces per ray [default: 20]
+      --roulette-depth <N>  Bounces before Russian roulette may end a path [default: 3]
+      --vfov <DEGREES>      Vertical field of view [default: 40]
+      --lookfrom <X,Y,Z>    Camera position
+      --lookat <X,Y,Z>      Point the camera looks at
+      --brightness <F>      Multiplier applied to every pixel [default: 1]
+
+Output:
+  -o, --output <PATH>       Output file, '-' for stdout [default: -]
+      --format <FORMAT>     ppm, png, pfm or hdr [default: from the extension, ppm on stdout]
+      --bit-depth <8|16>    Bits per channel of PNG files [default: 8]
+      --srgb                Encode PNG files with the sRGB curve instead of gamma 2
+
+Rendering:
+      --threads <N>         Number of render threads, 0 for all cores [default: 0]
+      --seed <N>            Seed of the random generator, for reproducible renders
+      --sampler <NAME>      independent, stratified, halton or sobol [default: independent]
+      --adaptive <ERROR>    Stop sampling pixels once their error is below ERROR, e.g. 0.01
+      --min-spp <N>         Samples of every pixel before --adaptive stops any [default: 16]
+      --sample-map <PATH>   Also write the number of samples of each pixel as an image
+      --bvh <METHOD>        BVH construction, median or sah [default: median]
+      --bvh-stats           Print the size and estimated cost of the BVH
+  -h, --help                Print this help
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scene {
+    Custom,
+    First,
+    Second,
+    Third,
+    Fourth,
+}
+
+impl Scene {
+    fn from_name(name: &str) -> Option<Scene> {
+        match name {
+            "custom" => Some(Scene::Custom),
+            "first" => Some(Scene::First),
+            "second" => Some(Scene::Second),
+            "third" => Some(Scene::Third),
+            "fourth" => Some(Scene::Fourth),
+            _ => None,
+        }
+    }
+}
+
+// Settings given on the command line; None means the scene default is kept.
+pub struct Options {
+    pub scene: Scene,
+    pub scene_file: Option<PathBuf>,
+    pub width: Option<usize>,
+    pub aspect_ratio: Option<f64>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub roulette_depth: Option<usize>,
+    pub vfov: Option<f64>,
+    pub lookfrom: Option<Point3<f64>>,
+    pub lookat: Option<Point3<f64>>,
+    pub brightness: Option<f64>,
+    pub output: Option<PathBuf>, // None writes to stdout
+    pub format: Option<ImageFormat>,
+    pub bit_depth: BitDepth,
+    pub transfer: Transfer,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerKind>,
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples_per_pixel: Option<usize>,
+    pub sample_map: Option<PathBuf>,
+    pub bvh: SplitMethod,
+    pub bvh_stats: bool,
+}
+
+pub enum Command {
+    Render(Box<Options>),
+    Help,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options {
+        scene: Scene::Custom,
+        scene_file: None,
+        width: None,
+        aspect_ratio: None,
+        samples_per_pixel: None,
+        max_depth: None,
+        roulette_depth: None,
+        vfov: None,
+        lookfrom: None,
+        lookat: None,
+        brightness: None,
+        output: None,
+        format: None,
+        bit_depth: BitDepth::Eight,
+        transfer: Transfer::Gamma2,
+        threads: None,
+        seed: None,
+        sampler: None,
+        adaptive_threshold: None,
+        min_samples_per_pixel: None,
+        sample_map: None,
+        bvh: SplitMethod::Median,
+        bvh_stats: false,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`.
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for '{}'", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-f" | "--first" => options.scene = Scene::First,
+            "-s" | "--second" => options.scene = Scene::Second,
+            "-t" | "--third" => options.scene = Scene::Third,
+            "-fo" | "--fourth" => options.scene = Scene::Fourth,
+            "--scene" => {
+                let name = value()?;
+                options.scene = Scene::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "unknown scene '{}', expected custom, first, second, third or fourth",
+                        name
+                    )
+                })?;
+            }
+            "--scene-file" => options.scene_file = Some(PathBuf::from(value()?)),
+            "--width" => options.width = Some(parse_positive(&flag, &value()?)?),
+            "--aspect" => options.aspect_ratio = Some(parse_aspect_ratio(&flag, &value()?)?),
+            "--spp" => options.samples_per_pixel = Some(parse_positive(&flag, &value()?)?),
+            "--depth" => options.max_depth = Some(parse_positive(&flag, &value()?)?),
+            "--roulette-depth" => options.roulette_depth = Some(parse_integer(&flag, &value()?)?),
+            "--vfov" => {
+                let vfov = parse_number(&flag, &value()?)?;
+                if !(vfov > 0.0 && vfov < 180.0) {
+                    return Err(format!(
+                        "invalid value '{}' for '{}': must be between 0 and 180 degrees",
+                        vfov, flag
+                    ));
+                }
+                options.vfov = Some(vfov);
+            }
+            "--lookfrom" => options.lookfrom = Some(parse_point(&flag, &value()?)?),
+            "--lookat" => options.lookat = Some(parse_point(&flag, &value()?)?),
+            "--brightness" => {
+                let brightness = parse_number(&flag, &value()?)?;
+                if brightness <= 0.0 {
+                    return Err(format!(
+                        "invalid value '{}' for '{}': must be positive",
+                        brightness, flag
+                    ));
+                }
+                options.brightness = Some(brightness);
+            }
+            "-o" | "--output" => {
+                let path = value()?;
+                options.output = if path == "-" {
+                    None
+                } else {
+                    Some(PathBuf::from(path))
+                };
+            }
+            "--format" => {
+                let name = value()?;
+                options.format = Some(ImageFormat::from_name(&name).ok_or_else(|| {
+                    format!("unknown format '{}', expected ppm, png, pfm or hdr", name)
+                })?);
+            }
+            "--bit-depth" => {
+                options.bit_depth = match value()?.as_str() {
+                    "8" => BitDepth::Eight,
+                    "16" => BitDepth::Sixteen,
+                    other => {
+                        return Err(format!(
+                            "invalid value '{}' for '{}': expected 8 or 16",
+                            other, flag
+                        ))
+                    }
+                }
+            }
+            "--srgb" => options.transfer = Transfer::Srgb,
+            "--threads" => options.threads = Some(parse_integer(&flag, &value()?)?),
+            "--seed" => options.seed = Some(parse_integer(&flag, &value()?)?),
+            "--sampler" => {
+                let name = value()?;
+                options.sampler = Some(SamplerKind::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "unknown sampler '{}', expected independent, stratified, halton or sobol",
+                        name
+                    )
+                })?);
+            }
+            "--adaptive" => {
+                let threshold = parse_number(&flag, &value()?)?;
+                if threshold <= 0.0 {
+                    return Err(format!(
+                        "invalid value '{}' for '{}': must be positive",
+                        threshold, flag
+                    ));
+                }
+                options.adaptive_threshold = Some(threshold);
+            }
+            "--min-spp" => options.min_samples_per_pixel = Some(parse_positive(&flag, &value()?)?),
+            "--sample-map" => options.sample_map = Some(PathBuf::from(value()?)),
+            "--bvh" => {
+                options.bvh = match value()?.as_str() {
+                    "median" => SplitMethod::Median,
+                    "sah" => SplitMethod::Sah,
+                    other => {
+                        return Err(format!(
+                            "invalid value '{}' for '{}': expected median or sah",
+                            other, flag
+                        ))
+                    }
+                }
+            }
+            "--bvh-stats" => options.bvh_stats = true,
+            _ => return Err(format!("unexpected argument '{}'", flag)),
+        }
+    }
+
+    // Without an explicit format, the output path must tell us which writer to use.
+    if let (Some(path), None) = (&options.output, options.format) {
+        if ImageFormat::from_path(path).is_none() {
+            return Err(format!(
+                "cannot guess the format of '{}', use a .ppm, .png, .pfm or .hdr extension or --format",
+                path.display()
+            ));
+        }
+    }
+    if let Some(path) = &options.sample_map {
+        if ImageFormat::from_path(path).is_none() {
+            return Err(format!(
+                "cannot guess the format of '{}', use a .ppm, .png, .pfm or .hdr extension",
+                path.display()
+            ));
+        }
+    }
+
+    Ok(Command::Render(Box::new(options)))
+}
+
+fn parse_integer<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "invalid value '{}' for '{}': expected a non-negative integer",
+            value, flag
+        )
+    })
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<usize, String> {
+    match parse_integer(flag, value)? {
+        0 => Err(format!(
+            "invalid value '{}' for '{}': must be at least 1",
+            value, flag
+        )),
+        n => Ok(n),
+    }
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!(
+            "invalid value '{}' for '{}': expected a number",
+            value, flag
+        )),
+    }
+}
+
+fn parse_aspect_ratio(flag: &str, value: &str) -> Result<f64, String> {
+    let ratio = match value.split_once([':', '/']) {
+        Some((w, h)) => parse_number(flag, w)? / parse_number(flag, h)?,
+        None => parse_number(flag, value)?,
+    };
+    if !(ratio.is_finite() && ratio > 0.0) {
+        return Err(format!(
+            "invalid value '{}' for '{}': must be a positive ratio",
+            value, flag
+        ));
+    }
+    Ok(ratio)
+}
+
+fn parse_point(flag: &str, value: &str) -> Result<Point3<f64>, String> {
+    let coords: Vec<&str> = value.split(',').collect();
+    if coords.len() != 3 {
+        return Err(format!(
+            "invalid value '{}' for '{}': expected X,Y,Z",
+            value, flag
+        ));
+    }
+    Ok(Point3::new(
+        parse_number(flag, coords[0].trim())?,
+        parse_number(flag, coords[1].trim())?,
+        parse_number(flag, coords[2].trim())?,
+    ))
+}
@@ -1,3 +1,5 @@
+use std::io::{self, Write};
+
 use na::Vector3;
 use nalgebra as na;
 
@@ -11,7 +13,44 @@ pub fn linear_to_gamma(linear_component: f64) -> f64 {
     }
 }
 
-pub fn write_color(color: &Vector3<f64>) {
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0.0031308 {
+        12.92 * linear_component.max(0.0)
+    } else {
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(srgb_component: f64) -> f64 {
+    if srgb_component <= 0.04045 {
+        srgb_component / 12.92
+    } else {
+        ((srgb_component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Relative luminance of a linear color, with the Rec. 709 primaries.
+pub fn luminance(color: &Vector3<f64>) -> f64 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+// Curve used to encode linear radiance into display values before quantizing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Gamma2,
+    Srgb,
+}
+
+impl Transfer {
+    pub fn encode(&self, linear_component: f64) -> f64 {
+        match self {
+            Transfer::Gamma2 => linear_to_gamma(linear_component),
+            Transfer::Srgb => linear_to_srgb(linear_component),
+        }
+    }
+}
+
+pub fn write_color(out: &mut impl Write, color: &Vector3<f64>) -> io::Result<()> {
     let mut r = color[0];
     let mut g = color[1];
     let mut b = color[2];
@@ -27,8 +66,7 @@ pub fn write_color(color: &Vector3<f64>) {
     let gbyte = (256.0 * intensity.clamp(g)) as i32;
     let bbyte = (256.0 * intensity.clamp(b)) as i32;
 
-    println!("{} {} {}", rbyte, gbyte, bbyte);
+    writeln!(out, "{} {} {}", rbyte, gbyte, bbyte)
 }
 
-
 pub type Color = Vector3<f64>;
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use na::Vector3;
+
+use crate::{
+    aabb::Aabb,
+    color::Color,
+    hash::{hash, hash_float},
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::{Isotropic, Material},
+    ray::Ray,
+    texture::Texture,
+};
+
+// Volume of constant density filling a boundary, such as fog or smoke. A ray going
+// through it scatters after an exponentially distributed distance, so thicker volumes
+// and denser media are more opaque. The boundary must be closed and convex (a sphere or
+// a box): a ray is only assumed to enter it and leave it once.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: Color) -> ConstantMedium {
+        ConstantMedium::from_material(boundary, density, Arc::new(Isotropic::new(albedo)))
+    }
+
+    pub fn from_texture(
+        boundary: Arc<dyn Hittable>,
+        density: f64,
+        tex: Arc<dyn Texture>,
+    ) -> ConstantMedium {
+        ConstantMedium::from_material(boundary, density, Arc::new(Isotropic::from_texture(tex)))
+    }
+
+    // `phase_function` decides how light scatters inside, usually an Isotropic material.
+    pub fn from_material(
+        boundary: Arc<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Find where the line enters and leaves the boundary, even behind the origin, so
+        // that rays starting inside the volume work too.
+        let mut rec1 = HitRecord::default();
+        let mut rec2 = HitRecord::default();
+        if !self.boundary.hit(r, Interval::UNIVERSE, &mut rec1) {
+            return false;
+        }
+        if !self
+            .boundary
+            .hit(r, Interval::new(rec1.t + 0.0001, f64::INFINITY), &mut rec2)
+        {
+            return false;
+        }
+
+        let t1 = rec1.t.max(ray_t.min).max(0.0);
+        let t2 = rec2.t.min(ray_t.max);
+        if t1 >= t2 {
+            return false;
+        }
+
+        let ray_length = r.direction().norm();
+        let distance_inside_boundary = (t2 - t1) * ray_length;
+        // The ray's sample is mixed with where it enters, so that volumes crossed by the
+        // same ray scatter independently of each other. 1 - u is never 0.
+        let u = hash_float(hash(&[r.sample().to_bits(), rec1.t.to_bits()]));
+        let hit_distance = self.neg_inv_density * (1.0 - u).ln();
+        if hit_distance > distance_inside_boundary {
+            return false;
+        }
+
+        rec.t = t1 + hit_distance / ray_length;
+        rec.p = r.at(rec.t);
+        rec.normal = Vector3::x(); // arbitrary, the phase function ignores it
+        rec.front_face = true;
+        rec.mat = self.phase_function.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable_list::HittableList, material::DefaultMaterial, sphere::Sphere};
+    use na::Point3;
+
+    fn fog(center: Point3<f64>) -> Arc<dyn Hittable> {
+        let boundary = Arc::new(Sphere::new(center, 0.5, Arc::new(DefaultMaterial::new())));
+        Arc::new(ConstantMedium::new(boundary, 1.0, Color::repeat(1.0)))
+    }
+
+    #[test]
+    fn volumes_on_one_ray_scatter_independently() {
+        // Two volumes one unit thick, one after the other along the ray.
+        let mut world = HittableList::new();
+        world.add(fog(Point3::new(0.0, 0.0, 0.0)));
+        world.add(fog(Point3::new(5.0, 0.0, 0.0)));
+
+        let n = 20000;
+        let mut hits = [0; 2];
+        for i in 0..n {
+            let r = Ray::new(Point3::new(-2.0, 0.0, 0.0), Vector3::x())
+                .with_sample((i as f64 + 0.5) / n as f64);
+            let mut rec = HitRecord::default();
+            if world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                hits[(rec.p.x > 2.5) as usize] += 1;
+
+                // The same ray always scatters at the same point.
+                let mut again = HitRecord::default();
+                assert!(world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut again));
+                assert_eq!(again.p, rec.p);
+            }
+        }
+
+        let absorbed = 1.0 - (-1.0f64).exp();
+        let expected = [absorbed, (1.0 - absorbed) * absorbed];
+        for (hits, expected) in hits.iter().zip(expected) {
+            let fraction = *hits as f64 / n as f64;
+            assert!(
+                (fraction - expected).abs() < 0.02,
+                "{} scattered, expected {}",
+                fraction,
+                expected
+            );
+        }
+    }
+}
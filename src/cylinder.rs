@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use na::{Point3, Vector3};
 use crate::{
     aabb::Aabb,
@@ -12,12 +12,12 @@ pub struct Cylinder {
     base_center: Point3<f64>,
     height: f64,
     radius: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
     bbox: Aabb,
 }
 
 impl Cylinder {
-    pub fn new(base_center: Point3<f64>, height: f64, radius: f64, mat: Rc<dyn Material>) -> Cylinder {
+    pub fn new(base_center: Point3<f64>, height: f64, radius: f64, mat: Arc<dyn Material>) -> Cylinder {
         let mut bbox = Aabb::new();
         bbox.aabb(
             &Point3::new(base_center.x - radius, base_center.y, base_center.z - radius),
@@ -0,0 +1,26 @@
+use crate::color::Color;
+
+// Linear RGB radiance of every pixel, stored row by row from the top-left corner.
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); width * height],
+        }
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> &Color {
+        &self.pixels[j * self.width + i]
+    }
+
+    pub fn set(&mut self, i: usize, j: usize, color: Color) {
+        self.pixels[j * self.width + i] = color;
+    }
+}
@@ -0,0 +1,22 @@
+// Hash of several values, for the samplers and wherever a fixed, portable stream of random
+// bits is needed.
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x243f_6a88_85a3_08d3, |h, &value| {
+        mix_bits(h ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15)
+    })
+}
+
+// Final mix of SplitMix64: every input bit affects every output bit.
+pub(crate) fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+// Uniform number in [0, 1) from the top 53 bits of a hash.
+pub(crate) fn hash_float(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -1,11 +1,13 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::aabb::Aabb;
-use crate::degrees_to_radians;
+use crate::instance::Instance;
 use crate::interval::Interval;
 use crate::material::{DefaultMaterial, Material};
 use crate::na::{Point3, Vector3};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::transform::Transform;
 
 #[derive(Clone)]
 pub struct HitRecord {
@@ -13,24 +15,26 @@ pub struct HitRecord {
     pub normal: Vector3<f64>,
     pub t: f64,
     pub front_face: bool,
-    pub mat: Rc<dyn Material>,
+    pub mat: Arc<dyn Material>,
     pub u: f64,
     pub v: f64,
 }
 
-impl HitRecord {
-    pub fn default() -> HitRecord {
+impl Default for HitRecord {
+    fn default() -> HitRecord {
         HitRecord {
             p: Point3::new(0.0, 0.0, 0.0),
             normal: Vector3::new(0.0, 0.0, 0.0),
             t: 0.0,
             front_face: false,
-            mat: Rc::new(DefaultMaterial::new()),
+            mat: Arc::new(DefaultMaterial::new()),
             u: 0.0,
             v: 0.0,
         }
     }
+}
 
+impl HitRecord {
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vector3<f64>) {
         self.front_face = r.direction().dot(&outward_normal) < 0.0;
         self.normal = if self.front_face {
@@ -41,36 +45,81 @@ impl HitRecord {
     }
 }
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self) -> &Aabb;
+
+    // Light sampling, for the objects that can be put in the list of lights given to
+    // Camera::render. `pdf_value` is the density, per unit solid angle, with which `random`
+    // picks the direction of `r` from its origin, or 0 when the ray misses the object.
+    fn pdf_value(&self, _r: &Ray) -> f64 {
+        0.0
+    }
+
+    // Random direction from `origin` toward a point of the object at the given time,
+    // drawn from `sampler`.
+    fn random(
+        &self,
+        _origin: &Point3<f64>,
+        _time: f64,
+        _sampler: &mut dyn Sampler,
+    ) -> Vector3<f64> {
+        Vector3::x()
+    }
 }
 
 pub struct Translate {
-    object: Rc<dyn Hittable>,
+    object: Arc<dyn Hittable>,
     offset: Vector3<f64>,
+    velocity: Vector3<f64>, // distance covered between time 0 and time 1
     bbox: Aabb,
 }
 
 impl Translate {
-    pub fn new(object: Rc<dyn Hittable>, offset: Vector3<f64>) -> Translate {
+    pub fn new(object: Arc<dyn Hittable>, offset: Vector3<f64>) -> Translate {
         // let bbox = object.bounding_box() + offset;
         let bbox = object.bounding_box() + &offset;
         Translate {
             object,
             offset,
+            velocity: Vector3::zeros(),
+            bbox,
+        }
+    }
+
+    // Moves the object in a straight line from `offset1` at time 0 to `offset2` at time 1.
+    // It stays still before and after.
+    pub fn new_moving(
+        object: Arc<dyn Hittable>,
+        offset1: Vector3<f64>,
+        offset2: Vector3<f64>,
+    ) -> Translate {
+        let bbox = Aabb::aabb_from_boxes(
+            &(object.bounding_box() + &offset1),
+            &(object.bounding_box() + &offset2),
+        );
+        Translate {
+            object,
+            offset: offset1,
+            velocity: offset2 - offset1,
             bbox,
         }
     }
+
+    fn offset_at(&self, time: f64) -> Vector3<f64> {
+        self.offset + time.clamp(0.0, 1.0) * self.velocity
+    }
 }
 
 impl Hittable for Translate {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let offset_r = Ray::new(r.origin() - self.offset, *r.direction());
+        let offset = self.offset_at(r.time());
+        let offset_r = Ray::new_with_time(r.origin() - offset, *r.direction(), r.time())
+            .with_sample(r.sample());
         if !self.object.hit(&offset_r, ray_t, rec) {
             return false;
         }
-        rec.p += self.offset;
+        rec.p += offset;
         true
     }
 
@@ -78,268 +127,110 @@ impl Hittable for Translate {
         // self.object.bounding_box()
         &self.bbox
     }
-}
 
-pub struct RotateY {
-    object: Rc<dyn Hittable>,
-    sin_theta: f64,
-    cos_theta: f64,
-    bbox: Aabb,
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let offset = self.offset_at(r.time());
+        let offset_r = Ray::new_with_time(r.origin() - offset, *r.direction(), r.time())
+            .with_sample(r.sample());
+        self.object.pdf_value(&offset_r)
+    }
+
+    fn random(&self, origin: &Point3<f64>, time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let offset_origin = origin - self.offset_at(time);
+        self.object.random(&offset_origin, time, sampler)
+    }
 }
 
-impl RotateY {
-    pub fn new(object: Rc<dyn Hittable>, angle: f64) -> RotateY {
-        let radians = degrees_to_radians(angle);
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let bbox = object.bounding_box();
-
-        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-        let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
-
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f64 * bbox.x.get().max + (1 - i) as f64 * bbox.x.get().min;
-                    let y = j as f64 * bbox.y.get().max + (1 - j) as f64 * bbox.y.get().min;
-                    let z = k as f64 * bbox.z.get().max + (1 - k) as f64 * bbox.z.get().min;
-
-                    let newx = cos_theta * x + sin_theta * z;
-                    let newz = -sin_theta * x + cos_theta * z;
-
-                    let tester = Vector3::new(newx, y, newz);
-
-                    for c in 0..3 {
-                        min[c] = min[c].min(tester[c]);
-                        max[c] = max[c].max(tester[c]);
-                    }
-                }
-            }
-        }
-        let mut bbox = Aabb::new();
-        bbox.aabb(&min, &max);
+// The rotations that came before Transform, kept so existing code still builds, with
+// their old directions: seen with the axis pointing toward the viewer, RotateX and RotateZ
+// turn clockwise, the opposite of Transform::rotate_x and rotate_z, and RotateY
+// counterclockwise. `new` panics if the angle is not finite.
+#[deprecated(note = "use Transform::identity(object).rotate_x(-angle)")]
+pub struct RotateX(Instance);
 
-        RotateY {
-            object,
-            sin_theta,
-            cos_theta,
-            bbox,
-        }
+#[allow(deprecated)]
+impl RotateX {
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> RotateX {
+        let instance = Transform::identity(object).rotate_x(-angle).into_instance();
+        RotateX(instance.expect("rotation angle is not finite"))
     }
 }
 
-impl Hittable for RotateY {
+#[allow(deprecated)]
+impl Hittable for RotateX {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let origin = Point3::new(
-            self.cos_theta * r.origin().x - self.sin_theta * r.origin().z,
-            r.origin().y,
-            self.sin_theta * r.origin().x + self.cos_theta * r.origin().z,
-        );
-
-        let direction = Vector3::new(
-            self.cos_theta * r.direction().x - self.sin_theta * r.direction().z,
-            r.direction().y,
-            self.sin_theta * r.direction().x + self.cos_theta * r.direction().z,
-        );
-
-        let rotated_r = Ray::new(origin, direction);
-
-        if !self.object.hit(&rotated_r, ray_t, rec) {
-            return false;
-        }
-
-        rec.p = Point3::new(
-            self.cos_theta * rec.p.x + self.sin_theta * rec.p.z,
-            rec.p.y,
-            -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z,
-        );
+        self.0.hit(r, ray_t, rec)
+    }
 
-        rec.normal = Vector3::new(
-            self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z,
-            rec.normal.y,
-            -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z,
-        );
+    fn bounding_box(&self) -> &Aabb {
+        self.0.bounding_box()
+    }
 
-        true
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        self.0.pdf_value(r)
     }
 
-    fn bounding_box(&self) -> &Aabb {
-        &self.bbox
+    fn random(&self, origin: &Point3<f64>, time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        self.0.random(origin, time, sampler)
     }
 }
 
-pub struct RotateX {
-    object: Rc<dyn Hittable>,
-    sin_theta: f64,
-    cos_theta: f64,
-    bbox: Aabb,
-}
+#[deprecated(note = "use Transform::identity(object).rotate_y(angle)")]
+pub struct RotateY(Instance);
 
-impl RotateX {
-    pub fn new(object: Rc<dyn Hittable>, angle: f64) -> RotateX {
-        let radians = degrees_to_radians(angle);
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let bbox = object.bounding_box();
-
-        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-        let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
-
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f64 * bbox.x.get().max + (1 - i) as f64 * bbox.x.get().min;
-                    let y = j as f64 * bbox.y.get().max + (1 - j) as f64 * bbox.y.get().min;
-                    let z = k as f64 * bbox.z.get().max + (1 - k) as f64 * bbox.z.get().min;
-
-                    let newy = cos_theta * y + sin_theta * z;
-                    let newz = -sin_theta * y + cos_theta * z;
-
-                    let tester = Vector3::new(x, newy, newz);
-
-                    for c in 0..3 {
-                        min[c] = min[c].min(tester[c]);
-                        max[c] = max[c].max(tester[c]);
-                    }
-                }
-            }
-        }
-        let mut bbox = Aabb::new();
-        bbox.aabb(&min, &max);
-
-        RotateX {
-            object,
-            sin_theta,
-            cos_theta,
-            bbox,
-        }
+#[allow(deprecated)]
+impl RotateY {
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> RotateY {
+        let instance = Transform::identity(object).rotate_y(angle).into_instance();
+        RotateY(instance.expect("rotation angle is not finite"))
     }
 }
 
-impl Hittable for RotateX {
+#[allow(deprecated)]
+impl Hittable for RotateY {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let origin = Point3::new(
-            r.origin().x,
-            self.cos_theta * r.origin().y - self.sin_theta * r.origin().z,
-            self.sin_theta * r.origin().y + self.cos_theta * r.origin().z,
-        );
-
-        let direction = Vector3::new(
-            r.direction().x,
-            self.cos_theta * r.direction().y - self.sin_theta * r.direction().z,
-            self.sin_theta * r.direction().y + self.cos_theta * r.direction().z,
-        );
-
-        let rotated_r = Ray::new(origin, direction);
-
-        if !self.object.hit(&rotated_r, ray_t, rec) {
-            return false;
-        }
-
-        rec.p = Point3::new(
-            rec.p.x,
-            self.cos_theta * rec.p.y + self.sin_theta * rec.p.z,
-            -self.sin_theta * rec.p.y + self.cos_theta * rec.p.z,
-        );
+        self.0.hit(r, ray_t, rec)
+    }
 
-        rec.normal = Vector3::new(
-            rec.normal.x,
-            self.cos_theta * rec.normal.y + self.sin_theta * rec.normal.z,
-            -self.sin_theta * rec.normal.y + self.cos_theta * rec.normal.z,
-        );
+    fn bounding_box(&self) -> &Aabb {
+        self.0.bounding_box()
+    }
 
-        true
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        self.0.pdf_value(r)
     }
 
-    fn bounding_box(&self) -> &Aabb {
-        &self.bbox
+    fn random(&self, origin: &Point3<f64>, time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        self.0.random(origin, time, sampler)
     }
 }
 
-pub struct RotateZ {
-    object: Rc<dyn Hittable>,
-    sin_theta: f64,
-    cos_theta: f64,
-    bbox: Aabb,
-}
+#[deprecated(note = "use Transform::identity(object).rotate_z(-angle)")]
+pub struct RotateZ(Instance);
 
+#[allow(deprecated)]
 impl RotateZ {
-    pub fn new(object: Rc<dyn Hittable>, angle: f64) -> RotateZ {
-        let radians = degrees_to_radians(angle);
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let bbox = object.bounding_box();
-
-        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-        let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
-
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f64 * bbox.x.get().max + (1 - i) as f64 * bbox.x.get().min;
-                    let y = j as f64 * bbox.y.get().max + (1 - j) as f64 * bbox.y.get().min;
-                    let z = k as f64 * bbox.z.get().max + (1 - k) as f64 * bbox.z.get().min;
-
-                    let newx = cos_theta * x + sin_theta * y;
-                    let newy = -sin_theta * x + cos_theta * y;
-
-                    let tester = Vector3::new(newx, newy, z);
-
-                    for c in 0..3 {
-                        min[c] = min[c].min(tester[c]);
-                        max[c] = max[c].max(tester[c]);
-                    }
-                }
-            }
-        }
-        let mut bbox = Aabb::new();
-        bbox.aabb(&min, &max);
-
-        RotateZ {
-            object,
-            sin_theta,
-            cos_theta,
-            bbox,
-        }
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> RotateZ {
+        let instance = Transform::identity(object).rotate_z(-angle).into_instance();
+        RotateZ(instance.expect("rotation angle is not finite"))
     }
 }
 
+#[allow(deprecated)]
 impl Hittable for RotateZ {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let origin = Point3::new(
-            self.cos_theta * r.origin().x - self.sin_theta * r.origin().y,
-            self.sin_theta * r.origin().x + self.cos_theta * r.origin().y,
-            r.origin().z,
-        );
-
-        let direction = Vector3::new(
-            self.cos_theta * r.direction().x - self.sin_theta * r.direction().y,
-            self.sin_theta * r.direction().x + self.cos_theta * r.direction().y,
-            r.direction().z,
-        );
-
-        let rotated_r = Ray::new(origin, direction);
-
-        if !self.object.hit(&rotated_r, ray_t, rec) {
-            return false;
-        }
-
-        rec.p = Point3::new(
-            self.cos_theta * rec.p.x + self.sin_theta * rec.p.y,
-            -self.sin_theta * rec.p.x + self.cos_theta * rec.p.y,
-            rec.p.z,
-        );
+        self.0.hit(r, ray_t, rec)
+    }
 
-        rec.normal = Vector3::new(
-            self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.y,
-            -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.y,
-            rec.normal.z,
-        );
+    fn bounding_box(&self) -> &Aabb {
+        self.0.bounding_box()
+    }
 
-        true
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        self.0.pdf_value(r)
     }
 
-    fn bounding_box(&self) -> &Aabb {
-        &self.bbox
+    fn random(&self, origin: &Point3<f64>, time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        self.0.random(origin, time, sampler)
     }
 }
@@ -1,14 +1,17 @@
-use std::rc::Rc;
+use std::sync::Arc;
+
+use na::{Point3, Vector3};
 
 use crate::{
     aabb::Aabb,
     hittable::{HitRecord, Hittable},
     interval::Interval,
     ray::Ray,
+    sampler::Sampler,
 };
 
 pub struct HittableList {
-    pub objects: Vec<Rc<dyn Hittable>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
     pub bbox: Aabb,
 }
 
@@ -20,7 +23,7 @@ impl HittableList {
         }
     }
 
-    pub fn add(&mut self, object: Rc<dyn Hittable>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object.clone());
         self.bbox = Aabb::aabb_from_boxes(&self.bbox, object.bounding_box());
     }
@@ -30,6 +33,12 @@ impl HittableList {
     }
 }
 
+impl Default for HittableList {
+    fn default() -> HittableList {
+        HittableList::new()
+    }
+}
+
 impl Hittable for HittableList {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let mut tmp_rec = HitRecord::default();
@@ -49,4 +58,22 @@ impl Hittable for HittableList {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    // Sampling picks one of the objects at random, so the density is their average.
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.objects.iter().map(|object| object.pdf_value(r)).sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: &Point3<f64>, time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let count = self.objects.len();
+        if count == 0 {
+            return Vector3::x();
+        }
+        let index = ((sampler.get_1d() * count as f64) as usize).min(count - 1);
+        self.objects[index].random(origin, time, sampler)
+    }
 }
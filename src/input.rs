@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use crate::color::{srgb_to_linear, Color};
+use crate::framebuffer::Framebuffer;
+
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// Reads a PNG or PPM image into linear colors, decoding the sRGB values stored in the
+// file. The format is recognized from the first bytes, not from the extension.
+pub fn load(path: &Path) -> io::Result<Framebuffer> {
+    read_image(&fs::read(path)?)
+}
+
+pub fn read_image(bytes: &[u8]) -> io::Result<Framebuffer> {
+    if bytes.starts_with(PNG_SIGNATURE) {
+        read_png(bytes)
+    } else if bytes.starts_with(b"P3") || bytes.starts_with(b"P6") {
+        read_ppm(bytes)
+    } else {
+        Err(invalid_data("unknown image format, expected PNG or PPM"))
+    }
+}
+
+// Reads a PNG of any color type and bit depth. Alpha is ignored.
+pub fn read_png(bytes: &[u8]) -> io::Result<Framebuffer> {
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    // Expands palettes and depths below 8 bits, leaving 8 or 16 bit samples.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(invalid_data)?;
+
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| invalid_data("PNG image is too large"))?;
+    let mut data = vec![0; size];
+    let info = reader.next_frame(&mut data).map_err(invalid_data)?;
+
+    // With 8 or 16 bit samples, rows have no padding and the pixels are contiguous.
+    let data = &data[..info.buffer_size()];
+    let samples: Vec<f64> = match info.bit_depth {
+        png::BitDepth::Sixteen => data
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as f64 / 65535.0)
+            .collect(),
+        _ => data.iter().map(|&byte| byte as f64 / 255.0).collect(),
+    };
+    let gray = matches!(
+        info.color_type,
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha
+    );
+
+    let mut image = Framebuffer::new(info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    for (pixel, values) in image.pixels.iter_mut().zip(samples.chunks_exact(channels)) {
+        let color = if gray {
+            Color::repeat(values[0])
+        } else {
+            Color::new(values[0], values[1], values[2])
+        };
+        *pixel = color.map(srgb_to_linear);
+    }
+    Ok(image)
+}
+
+// Reads an ASCII (P3) or binary (P6) PPM with up to 16 bits per channel.
+pub fn read_ppm(bytes: &[u8]) -> io::Result<Framebuffer> {
+    let mut header = PpmHeader { bytes, pos: 2 };
+    let width = header.number()?;
+    let height = header.number()?;
+    let max_value = header.number()?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data(
+            "PPM maximum value must be between 1 and 65535",
+        ));
+    }
+
+    let count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(3))
+        .ok_or_else(|| invalid_data("PPM image is too large"))?;
+    let samples: Vec<usize> = if bytes[1] == b'3' {
+        (0..count)
+            .map(|_| header.number())
+            .collect::<io::Result<_>>()?
+    } else {
+        // A single whitespace byte separates the header from the binary data.
+        let data = bytes.get(header.pos + 1..).unwrap_or_default();
+        let sample_size = if max_value < 256 { 1 } else { 2 };
+        if data.len() < count * sample_size {
+            return Err(invalid_data("PPM image data is truncated"));
+        }
+        match sample_size {
+            1 => data[..count].iter().map(|&byte| byte as usize).collect(),
+            _ => data
+                .chunks_exact(2)
+                .take(count)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as usize)
+                .collect(),
+        }
+    };
+
+    let scale = 1.0 / max_value as f64;
+    let mut image = Framebuffer::new(width, height);
+    for (pixel, rgb) in image.pixels.iter_mut().zip(samples.chunks_exact(3)) {
+        *pixel = Color::from_fn(|c, _| srgb_to_linear((rgb[c].min(max_value) as f64) * scale));
+    }
+    Ok(image)
+}
+
+// Whitespace separated decimal numbers, with comments from '#' to the end of the line.
+struct PpmHeader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl PpmHeader<'_> {
+    fn number(&mut self) -> io::Result<usize> {
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'#') => {
+                    while self.bytes.get(self.pos).is_some_and(|&b| b != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| invalid_data("malformed PPM file"))
+    }
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
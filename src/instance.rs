@@ -0,0 +1,166 @@
+use std::fmt;
+use std::sync::Arc;
+
+use na::{Affine3, Matrix3, Matrix4, Point3, Vector3};
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    ray::Ray,
+    sampler::Sampler,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransformError {
+    NotAffine,
+    NotInvertible,
+    ZeroRotationAxis,
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransformError::NotAffine => write!(f, "transform matrix is not affine"),
+            TransformError::NotInvertible => write!(f, "transform is not invertible"),
+            TransformError::ZeroRotationAxis => write!(f, "rotation axis is zero"),
+        }
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+// One placement of a shared object, usually a mesh or a BVH of several objects, by any
+// affine transform. Rays are moved into the object's own space instead of moving the
+// object, so any number of instances share one copy of the geometry and of its
+// acceleration structure. Put the instances themselves in a BVHNode to get a two-level
+// hierarchy.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    object_to_world: Affine3<f64>,
+    world_to_object: Affine3<f64>,
+    normal_to_world: Matrix3<f64>, // inverse transpose of the linear part
+    solid_angle_scale: f64,        // |det| of the linear part of world_to_object
+    bbox: Aabb,
+}
+
+impl Instance {
+    // `object_to_world` must be an invertible affine transform: its last row is (0, 0, 0, 1).
+    pub fn new(
+        object: Arc<dyn Hittable>,
+        object_to_world: Matrix4<f64>,
+    ) -> Result<Instance, TransformError> {
+        if object_to_world.row(3) != Matrix4::identity().row(3) {
+            return Err(TransformError::NotAffine);
+        }
+        Instance::from_affine(object, Affine3::from_matrix_unchecked(object_to_world))
+    }
+
+    // `object_to_world` must be invertible, with finite coefficients.
+    pub fn from_affine(
+        object: Arc<dyn Hittable>,
+        object_to_world: Affine3<f64>,
+    ) -> Result<Instance, TransformError> {
+        let world_to_object = object_to_world
+            .try_inverse()
+            .filter(|inverse| {
+                object_to_world.matrix().iter().all(|c| c.is_finite())
+                    && inverse.matrix().iter().all(|c| c.is_finite())
+            })
+            .ok_or(TransformError::NotInvertible)?;
+
+        let linear = world_to_object.matrix().fixed_view::<3, 3>(0, 0);
+        let bbox = transform_bbox(object.bounding_box(), &object_to_world);
+
+        Ok(Instance {
+            object,
+            object_to_world,
+            world_to_object,
+            normal_to_world: linear.transpose(),
+            solid_angle_scale: linear.determinant().abs(),
+            bbox,
+        })
+    }
+
+    pub fn object_to_world(&self) -> &Affine3<f64> {
+        &self.object_to_world
+    }
+
+    pub fn world_to_object(&self) -> &Affine3<f64> {
+        &self.world_to_object
+    }
+
+    pub fn normal_to_world(&self) -> &Matrix3<f64> {
+        &self.normal_to_world
+    }
+
+    fn ray_to_object(&self, r: &Ray) -> Ray {
+        Ray::new_with_time(
+            self.world_to_object * r.origin(),
+            self.world_to_object * r.direction(),
+            r.time(),
+        )
+        .with_sample(r.sample())
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // The direction is not normalized, so distances along the ray are the same in both
+        // spaces and ray_t needs no conversion.
+        let local_r = self.ray_to_object(r);
+        if !self.object.hit(&local_r, ray_t, rec) {
+            return false;
+        }
+
+        // Transforming the normal by the inverse transpose keeps its dot product with the
+        // ray direction, so the front_face of the object-space hit still holds.
+        rec.p = r.at(rec.t);
+        rec.normal = (self.normal_to_world * rec.normal).normalize();
+        true
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        // The transform stretches solid angles: a unit direction d in world space becomes
+        // A * d in object space, and the density scales by |det A| / |A * d|³.
+        let object_pdf = self.object.pdf_value(&self.ray_to_object(r));
+        if object_pdf == 0.0 {
+            return 0.0;
+        }
+        let stretched = self.world_to_object * r.direction().normalize();
+        object_pdf * self.solid_angle_scale / stretched.norm().powi(3)
+    }
+
+    fn random(&self, origin: &Point3<f64>, time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let object_origin = self.world_to_object * origin;
+        let direction = self.object.random(&object_origin, time, sampler);
+        self.object_to_world * direction
+    }
+}
+
+// Box around the eight transformed corners of `bbox`.
+pub(crate) fn transform_bbox(bbox: &Aabb, transform: &Affine3<f64>) -> Aabb {
+    if bbox.x.min > bbox.x.max || bbox.y.min > bbox.y.max || bbox.z.min > bbox.z.max {
+        return Aabb::empty();
+    }
+
+    let mut result = Aabb::empty();
+    for corner in 0..8 {
+        let coord = |axis: usize| {
+            let interval = bbox.axis_interval(axis);
+            match corner & (1 << axis) {
+                0 => interval.min,
+                _ => interval.max,
+            }
+        };
+        let p = transform * Point3::new(coord(0), coord(1), coord(2));
+        let mut point_box = Aabb::new();
+        point_box.aabb(&p, &p);
+        result = Aabb::aabb_from_boxes(&result, &point_box);
+    }
+    result
+}
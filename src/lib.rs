@@ -1,4 +1,7 @@
-use rand::Rng;
+use std::cell::RefCell;
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
 
 pub extern crate nalgebra as na;
 
@@ -10,22 +13,48 @@ pub mod hittable;
 pub mod hittable_list;
 pub mod interval;
 pub mod ray;
+pub mod sampler;
 pub mod sphere;
 pub mod vec3;
+pub mod onb;
 pub mod material;
+pub mod texture;
+pub mod perlin;
 pub mod aabb;
 pub mod bvh;
 pub mod quad;
 pub mod cylinder;
+pub mod constant_medium;
+pub mod triangle;
+pub mod mesh;
+pub mod instance;
+pub mod transform;
+pub mod obj;
+pub mod framebuffer;
+pub mod input;
+pub mod output;
+pub mod scene;
+
+mod hash;
 
 // Returns the degrees equivalent of radians.
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * std::f64::consts::PI / 180.0
 }
 
+thread_local! {
+    // Each render thread owns its generator, so sampling never contends on shared state.
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+// Reseeds the random generator of the calling thread.
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
 // Returns a random real in [0,1).
 pub fn random_double() -> f64 {
-    rand::thread_rng().gen_range(0.0..1.0)
+    RNG.with(|rng| rng.borrow_mut().gen_range(0.0..1.0))
 }
 
 // Returns a random real in [min,max).
@@ -1,118 +1,248 @@
+mod cli;
+
 use std::env;
-use std::rc::Rc;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::sync::Arc;
 
+use cli::{Command, Scene};
 use rt::{
     bvh::BVHNode,
     camera::Camera,
     color::Color,
     cylinder::Cylinder,
-    hittable::{Hittable, RotateX, RotateY, RotateZ, Translate},
+    hittable::Hittable,
     hittable_list::HittableList,
     material::{
         create_light_material, create_standard_glass, create_standard_material,
         create_standard_metal, LightColor, StandardColor, StandardGlasses,
         StandardMetal,
     },
+    output::{save, write_image, ImageFormat, OutputOptions},
     quad::{box_, Quad},
+    scene::load_scene,
+    seed_rng,
     sphere::Sphere,
+    transform::Transform,
     Point3, Vector3,
 };
 
 fn main() {
-    // Image settings
-    const ASPECT_RATIO: f64 = 4.0 / 3.0;
-    const IMAGE_WIDTH: usize = 800;
-
-    // Camera settings
-    let mut cam = Camera::new(ASPECT_RATIO, IMAGE_WIDTH);
-    cam.samples_per_pixel = 1000;
-    cam.max_depth = 20;
-    cam.background = Color::new(0.0, 0.0, 0.0);
-    cam.brightness = 1.0;
-    cam.vfov = 40.0;
-    cam.defocus_angle = 0.0;
-    cam.focus_dist = 10.0;
-    cam.lookfrom = Point3::new(378.0, 278.0, -800.0);
-    cam.lookat = Point3::new(378.0, 278.0, 0.0);
-    cam.vup = Vector3::new(0.0, 1.0, 0.0);
-
-    // Scene initialization
-    let mut world: HittableList = HittableList::new();
-    cornell_box(&mut world);
-
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        1 => {
-            custom_scene(&mut world);
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Render(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\nFor more information, try '--help'.", err);
+            process::exit(2);
         }
-        2 => match args[1].as_str() {
-            "--first" | "-f" => {
+    };
+
+    let mut cam;
+    let mut world;
+    let mut lights;
+
+    if let Some(seed) = options.seed {
+        seed_rng(seed);
+    }
+
+    if let Some(path) = &options.scene_file {
+        match load_scene(path) {
+            Ok(scene) => {
+                cam = scene.camera;
+                world = scene.world;
+                lights = scene.lights;
+            }
+            Err(err) => {
+                eprintln!("error: {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+    } else {
+        // Image settings
+        const ASPECT_RATIO: f64 = 4.0 / 3.0;
+        const IMAGE_WIDTH: usize = 800;
+
+        // Camera settings
+        cam = Camera::new(ASPECT_RATIO, IMAGE_WIDTH);
+        cam.samples_per_pixel = 1000;
+        cam.max_depth = 20;
+        cam.background = Color::new(0.0, 0.0, 0.0);
+        cam.brightness = 1.0;
+        cam.vfov = 40.0;
+        cam.defocus_angle = 0.0;
+        cam.focus_dist = 10.0;
+        cam.lookfrom = Point3::new(378.0, 278.0, -800.0);
+        cam.lookat = Point3::new(378.0, 278.0, 0.0);
+        cam.vup = Vector3::new(0.0, 1.0, 0.0);
+
+        // Scene initialization
+        world = HittableList::new();
+        lights = HittableList::new();
+        cornell_box(&mut world, &mut lights);
+
+        match options.scene {
+            Scene::Custom => {
+                custom_scene(&mut world, &mut lights);
+            }
+            Scene::First => {
                 first_scene(&mut world);
             }
-            "--second" | "-s" => {
+            Scene::Second => {
                 second_scene(&mut world);
                 cam.brightness = 0.5;
             }
-            "--third" | "-t" => {
+            Scene::Third => {
                 third_scene(&mut world);
             }
-
-            "--fourth" | "-fo" => {
+            Scene::Fourth => {
                 third_scene(&mut world);
                 cam.vfov = 50.0;
                 cam.lookfrom = Point3::new(755.0, 555.0, 555.0);
                 cam.lookat = Point3::new(0.0, 0.0, 0.0);
                 cam.vup = Vector3::new(0.0, 1.0, 0.0);
             }
-            _ => {
-                eprintln!("Invalid scene name");
-                std::process::exit(1);
-            }
-        },
-        _ => {
-            eprintln!("Invalid number of arguments");
-            std::process::exit(1);
         }
     }
 
+    // Command line overrides
+    if let Some(width) = options.width {
+        cam.img_width = width;
+    }
+    if let Some(aspect_ratio) = options.aspect_ratio {
+        cam.aspect_ratio = aspect_ratio;
+    }
+    if let Some(samples_per_pixel) = options.samples_per_pixel {
+        cam.samples_per_pixel = samples_per_pixel;
+    }
+    if let Some(max_depth) = options.max_depth {
+        cam.max_depth = max_depth;
+    }
+    if let Some(roulette_depth) = options.roulette_depth {
+        cam.roulette_depth = roulette_depth;
+    }
+    if let Some(vfov) = options.vfov {
+        cam.vfov = vfov;
+    }
+    if let Some(lookfrom) = options.lookfrom {
+        cam.lookfrom = lookfrom;
+    }
+    if let Some(lookat) = options.lookat {
+        cam.lookat = lookat;
+    }
+    if let Some(brightness) = options.brightness {
+        cam.brightness = brightness;
+    }
+    if let Some(threads) = options.threads {
+        cam.threads = threads;
+    }
+    cam.seed = options.seed;
+    if let Some(sampler) = options.sampler {
+        cam.sampler = sampler;
+    }
+    if let Some(threshold) = options.adaptive_threshold {
+        cam.adaptive_threshold = threshold;
+    }
+    if let Some(min_samples_per_pixel) = options.min_samples_per_pixel {
+        cam.min_samples_per_pixel = min_samples_per_pixel;
+    }
+
+    // Adaptive renders count their progress in samples rather than scanlines.
+    cam.progress = if cam.adaptive_threshold > 0.0 {
+        Some(Box::new(|done, total| {
+            eprint!("\rSamples traced: {}% \r", done * 100 / total.max(1));
+            io::stderr().flush().unwrap();
+        }))
+    } else {
+        Some(Box::new(|done, total| {
+            eprint!("\rScanlines remaining: {} \r", total - done);
+            io::stderr().flush().unwrap();
+        }))
+    };
+
+    if cam.lookfrom == cam.lookat {
+        eprintln!("error: --lookfrom and --lookat must be different points");
+        process::exit(2);
+    }
+    let view = cam.lookat - cam.lookfrom;
+    if cam.vup.cross(&view).norm() <= 1e-9 * cam.vup.norm() * view.norm() {
+        eprintln!("error: the view from --lookfrom to --lookat must not be parallel to vup");
+        process::exit(2);
+    }
+
     // Setting
-    let bvh_world = BVHNode::new_from_list(&world);
-    cam.render(&bvh_world);
+    let bvh_world = BVHNode::new_from_list_with(&world, options.bvh);
+    if options.bvh_stats {
+        eprintln!("BVH: {}", bvh_world.stats());
+    }
+    let image = cam.render(&bvh_world, &lights);
+    eprintln!("\rDone.                           \n");
+
+    let output_options = OutputOptions {
+        format: options.format,
+        bit_depth: options.bit_depth,
+        transfer: options.transfer,
+    };
+    let written = match &options.output {
+        Some(path) => save(path, &image, &output_options),
+        None => {
+            let mut out = BufWriter::new(io::stdout().lock());
+            let format = options.format.unwrap_or(ImageFormat::Ppm);
+            write_image(&mut out, &image, format, &output_options).and_then(|_| out.flush())
+        }
+    };
+    if let Err(err) = written {
+        eprintln!("Failed to write image: {}", err);
+        process::exit(1);
+    }
+
+    if let Some(path) = &options.sample_map {
+        let map_options = OutputOptions {
+            format: None,
+            ..output_options
+        };
+        if let Err(err) = save(path, &cam.sample_count_image(), &map_options) {
+            eprintln!("Failed to write sample map: {}", err);
+            process::exit(1);
+        }
+    }
 }
 
-fn cornell_box(world: &mut HittableList) {
+fn cornell_box(world: &mut HittableList, lights: &mut HittableList) {
     let red = create_standard_material(StandardColor::Red);
     let green = create_standard_material(StandardColor::Green);
     let white = create_standard_material(StandardColor::White);
     let light = create_light_material(LightColor::White);
 
     // Box
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         Point3::new(755.0, 0.0, 0.0),
         Vector3::new(0.0, 555.0, 0.0),
         Vector3::new(0.0, 0.0, 555.0),
         red,
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         Point3::new(0.0, 0.0, 0.0),
         Vector3::new(0.0, 555.0, 0.0),
         Vector3::new(0.0, 0.0, 555.0),
         green,
     )));
 
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         Point3::new(0.0, 0.0, 0.0),
         Vector3::new(755.0, 0.0, 0.0),
         Vector3::new(0.0, 0.0, 555.0),
         white.clone(),
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         Point3::new(755.0, 555.0, 555.0),
         Vector3::new(-755.0, 0.0, 0.0),
         Vector3::new(0.0, 0.0, -555.0),
         white.clone(),
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         Point3::new(0.0, 0.0, 555.0),
         Vector3::new(755.0, 0.0, 0.0),
         Vector3::new(0.0, 555.0, 0.0),
@@ -120,15 +250,17 @@ fn cornell_box(world: &mut HittableList) {
     )));
 
     // Light
-    world.add(Rc::new(Quad::new(
+    let ceiling_light: Arc<dyn Hittable> = Arc::new(Quad::new(
         Point3::new(625.0, 554.0, 332.0),
         Vector3::new(-500.0, 0.0, 0.0),
         Vector3::new(0.0, 0.0, -105.0),
         light,
-    )));
+    ));
+    world.add(ceiling_light.clone());
+    lights.add(ceiling_light);
 }
 
-fn custom_scene(world: &mut HittableList) {
+fn custom_scene(world: &mut HittableList, lights: &mut HittableList) {
     let glass = create_standard_glass(StandardGlasses::Glass);
     let diamond = create_standard_glass(StandardGlasses::Diamond);
     let gold = create_standard_metal(StandardMetal::Gold, 0.3);
@@ -141,38 +273,40 @@ fn custom_scene(world: &mut HittableList) {
     let purple = create_standard_material(StandardColor::Purple);
 
     // Spheres
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         Point3::new(200.0, 100.0, 300.0),
         100.0,
         aluminium.clone(),
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         Point3::new(375.0, 150.0, 300.0),
         50.0,
         diamond,
     )));
 
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         Point3::new(600.0, 75.0, 400.0),
         75.0,
         glass,
     )));
-    world.add(Rc::new(Sphere::new(
+    let lamp: Arc<dyn Hittable> = Arc::new(Sphere::new(
         Point3::new(150.0, 10.0, 150.0),
         10.0,
         light,
-    )));
-    world.add(Rc::new(Sphere::new(
+    ));
+    world.add(lamp.clone());
+    lights.add(lamp);
+    world.add(Arc::new(Sphere::new(
         Point3::new(700.0, 20.0, 50.0),
         20.0,
         silver,
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         Point3::new(100.0, 50.0, 200.0),
         50.0,
         copper,
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         Point3::new(280.0, 25.0, 50.0),
         25.0,
         yellow,
@@ -180,37 +314,43 @@ fn custom_scene(world: &mut HittableList) {
 
 
     // Cubes
-    let mut cube1: Rc<dyn Hittable> = box_(
+    let cube1 = box_(
         Point3::new(0.0, 0.0, 0.0),
         Point3::new(100.0, 100.0, 100.0),
         gold,
     );
-    cube1 = Rc::new(RotateY::new(cube1, 45.0));
-    cube1 = Rc::new(Translate::new(cube1, Vector3::new(300.0, 0.0, 300.0)));
-    world.add(cube1);
+    world.add(place(
+        Transform::identity(cube1)
+            .rotate_y(45.0)
+            .translate(Vector3::new(300.0, 0.0, 300.0)),
+    ));
 
-    let mut cube2: Rc<dyn Hittable> = box_(
+    let cube2 = box_(
         Point3::new(0.0, 0.0, 0.0),
         Point3::new(50.0, 50.0, 50.0),
         aluminium.clone(),
     );
-    cube2 = Rc::new(RotateY::new(cube2, 30.0));
-    cube2 = Rc::new(Translate::new(cube2, Vector3::new(500.0, 0.0, 100.0)));
-    world.add(cube2);
+    world.add(place(
+        Transform::identity(cube2)
+            .rotate_y(30.0)
+            .translate(Vector3::new(500.0, 0.0, 100.0)),
+    ));
 
-    let mut cube3: Rc<dyn Hittable> = box_(
+    let cube3 = box_(
         Point3::new(0.0, 0.0, 0.0),
         Point3::new(50.0, 100.0, 50.0),
         blue,
     );
-    cube3 = Rc::new(RotateY::new(cube3, 30.0));
-    cube3 = Rc::new(Translate::new(cube3, Vector3::new(500.0, 0.0, 100.0)));
-    world.add(cube3);
+    world.add(place(
+        Transform::identity(cube3)
+            .rotate_y(30.0)
+            .translate(Vector3::new(500.0, 0.0, 100.0)),
+    ));
 
     
 
     // Cylinders
-    let cylinder1: Rc<dyn Hittable> = Rc::new(Cylinder::new(
+    let cylinder1: Arc<dyn Hittable> = Arc::new(Cylinder::new(
         Point3::new(650.0, 0.0, 150.0),
         200.0,
         20.0,
@@ -218,23 +358,24 @@ fn custom_scene(world: &mut HittableList) {
     ));
     world.add(cylinder1);
 
-    let mut cylinder2: Rc<dyn Hittable> = Rc::new(Cylinder::new(
+    let cylinder2: Arc<dyn Hittable> = Arc::new(Cylinder::new(
         Point3::new(0.0, 0.0, 0.0),
         100.0,
         25.0,
         aluminium.clone(),
     ));
-
-    cylinder2 = Rc::new(RotateX::new(cylinder2, 90.0));
-    cylinder2 = Rc::new(RotateY::new(cylinder2, 45.0));
-    cylinder2 = Rc::new(Translate::new(cylinder2, Vector3::new(400.0, 25.0, 100.0)));
-    world.add(cylinder2);
+    world.add(place(
+        Transform::identity(cylinder2)
+            .rotate_x(-90.0)
+            .rotate_y(45.0)
+            .translate(Vector3::new(400.0, 25.0, 100.0)),
+    ));
 
 }
 
 fn first_scene(world: &mut HittableList) {
     // Sphere
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         Point3::new(400.0, 90.0, 190.0),
         90.0,
         create_standard_material(StandardColor::Grey),
@@ -246,28 +387,31 @@ fn second_scene(world: &mut HittableList) {
     let blue = create_standard_material(StandardColor::Blue);
 
     // Flat plane
-    let mut plane: Rc<dyn Hittable> = Rc::new(Quad::new(
+    let plane: Arc<dyn Hittable> = Arc::new(Quad::new(
         Point3::new(-100.0, 50.0, 0.0),
         Vector3::new(200.0, 0.0, 0.0),
         Vector3::new(0.0, 200.0, 0.0),
         blue,
     ));
-    plane = Rc::new(RotateY::new(plane, -75.0));
-    plane = Rc::new(RotateX::new(plane, 15.0));
-    plane = Rc::new(RotateZ::new(plane, -30.0));
-
-    plane = Rc::new(Translate::new(plane, Vector3::new(200.0, 100.0, 200.0)));
-    world.add(plane);
+    world.add(place(
+        Transform::identity(plane)
+            .rotate_y(-75.0)
+            .rotate_x(-15.0)
+            .rotate_z(30.0)
+            .translate(Vector3::new(200.0, 100.0, 200.0)),
+    ));
 
     // Cube
-    let mut cube: Rc<dyn Hittable> = box_(
+    let cube = box_(
         Point3::new(0.0, 0.0, 0.0),
         Point3::new(165.0, 165.0, 165.0),
         grey,
     );
-    cube = Rc::new(RotateY::new(cube, 15.0));
-    cube = Rc::new(Translate::new(cube, Vector3::new(330.0, 0.0, 255.0)));
-    world.add(cube);
+    world.add(place(
+        Transform::identity(cube)
+            .rotate_y(15.0)
+            .translate(Vector3::new(330.0, 0.0, 255.0)),
+    ));
 }
 
 fn third_scene(world: &mut HittableList) {
@@ -277,24 +421,26 @@ fn third_scene(world: &mut HittableList) {
     let purple = create_standard_material(StandardColor::Purple);
 
     // Sphere
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         Point3::new(600.0, 90.0, 100.0),
         90.0,
         grey,
     )));
 
     // Cube
-    let mut cube: Rc<dyn Hittable> = box_(
+    let cube = box_(
         Point3::new(0.0, 0.0, 0.0),
         Point3::new(165.0, 165.0, 165.0),
         blue,
     );
-    cube = Rc::new(RotateY::new(cube, 15.0));
-    cube = Rc::new(Translate::new(cube, Vector3::new(330.0, 0.0, 255.0)));
-    world.add(cube);
+    world.add(place(
+        Transform::identity(cube)
+            .rotate_y(15.0)
+            .translate(Vector3::new(330.0, 0.0, 255.0)),
+    ));
 
     // Cylinder
-    let cylinder: Rc<dyn Hittable> = Rc::new(Cylinder::new(
+    let cylinder: Arc<dyn Hittable> = Arc::new(Cylinder::new(
         Point3::new(300.0, 0.0, 50.0),
         200.0,
         30.0,
@@ -303,15 +449,26 @@ fn third_scene(world: &mut HittableList) {
     world.add(cylinder);
 
     // Flat plane
-    let mut plane: Rc<dyn Hittable> = Rc::new(Quad::new(
+    let plane: Arc<dyn Hittable> = Arc::new(Quad::new(
         Point3::new(-100.0, 50.0, 0.0),
         Vector3::new(200.0, 0.0, 0.0),
         Vector3::new(0.0, 200.0, 0.0),
         purple,
     ));
-    plane = Rc::new(RotateY::new(plane, -75.0));
-    plane = Rc::new(RotateX::new(plane, 15.0));
-    plane = Rc::new(RotateZ::new(plane, -30.0));
-    plane = Rc::new(Translate::new(plane, Vector3::new(200.0, 100.0, 200.0)));
-    world.add(plane);
+    world.add(place(
+        Transform::identity(plane)
+            .rotate_y(-75.0)
+            .rotate_x(-15.0)
+            .rotate_z(30.0)
+            .translate(Vector3::new(200.0, 100.0, 200.0)),
+    ));
+}
+
+// Finishes the transform of an object of the built-in scenes.
+fn place(transform: Transform) -> Arc<dyn Hittable> {
+    Arc::new(
+        transform
+            .into_instance()
+            .expect("built-in scene transforms are invertible"),
+    )
 }
@@ -1,10 +1,12 @@
-use std::rc::Rc;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-use na::Point3;
+use na::{Point3, Vector3};
 
-use crate::random_double;
-use crate::vec3::{random_unit_vector, reflect, refract, Vector3Ext};
-use crate::{color::Color, hittable::HitRecord, ray::Ray};
+use crate::sampler::Sampler;
+use crate::texture::{SolidColor, Texture};
+use crate::vec3::{reflect, refract, sample_unit_vector, Vector3Ext};
+use crate::{color::Color, hittable::HitRecord};
 
 pub enum DefaultPalette {
     DefaultMaterial,
@@ -20,13 +22,13 @@ pub fn create_material(
     fuzz: f64,
     refraction_index: f64,
     emit: Color,
-) -> Rc<dyn Material> {
+) -> Arc<dyn Material> {
     match palette {
-        DefaultPalette::DefaultMaterial => Rc::new(DefaultMaterial::new()),
-        DefaultPalette::Lambertian => Rc::new(Lambertian::new(color)),
-        DefaultPalette::Metal => Rc::new(Metal::new(color, fuzz)),
-        DefaultPalette::Dielectric => Rc::new(Dielectric::new(refraction_index)),
-        DefaultPalette::DiffuseLight => Rc::new(DiffuseLight::new(emit)),
+        DefaultPalette::DefaultMaterial => Arc::new(DefaultMaterial::new()),
+        DefaultPalette::Lambertian => Arc::new(Lambertian::new(color)),
+        DefaultPalette::Metal => Arc::new(Metal::new(color, fuzz)),
+        DefaultPalette::Dielectric => Arc::new(Dielectric::new(refraction_index)),
+        DefaultPalette::DiffuseLight => Arc::new(DiffuseLight::new(emit)),
     }
 }
 
@@ -41,16 +43,16 @@ pub enum StandardColor {
     Purple,
 }
 
-pub fn create_standard_material(palette: StandardColor) -> Rc<dyn Material> {
+pub fn create_standard_material(palette: StandardColor) -> Arc<dyn Material> {
     match palette {
-        StandardColor::Red => Rc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05))),
-        StandardColor::Green => Rc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15))),
-        StandardColor::Blue => Rc::new(Lambertian::new(Color::new(0.1, 0.1, 0.7))),
-        StandardColor::White => Rc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))),
-        StandardColor::Black => Rc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0))),
-        StandardColor::Grey => Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
-        StandardColor::Yellow => Rc::new(Lambertian::new(Color::new(0.9, 0.9, 0.0))),
-        StandardColor::Purple => Rc::new(Lambertian::new(Color::new(0.5, 0.0, 0.5))),
+        StandardColor::Red => Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05))),
+        StandardColor::Green => Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15))),
+        StandardColor::Blue => Arc::new(Lambertian::new(Color::new(0.1, 0.1, 0.7))),
+        StandardColor::White => Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))),
+        StandardColor::Black => Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0))),
+        StandardColor::Grey => Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        StandardColor::Yellow => Arc::new(Lambertian::new(Color::new(0.9, 0.9, 0.0))),
+        StandardColor::Purple => Arc::new(Lambertian::new(Color::new(0.5, 0.0, 0.5))),
     }
 }
 
@@ -61,12 +63,12 @@ pub enum StandardMetal {
     Aluminium,
 }
 
-pub fn create_standard_metal(palette: StandardMetal, fuzz: f64) -> Rc<dyn Material> {
+pub fn create_standard_metal(palette: StandardMetal, fuzz: f64) -> Arc<dyn Material> {
     match palette {
-        StandardMetal::Gold => Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), fuzz)),
-        StandardMetal::Silver => Rc::new(Metal::new(Color::new(0.9, 0.9, 0.9), fuzz)),
-        StandardMetal::Copper => Rc::new(Metal::new(Color::new(0.72, 0.45, 0.2), fuzz)),
-        StandardMetal::Aluminium => Rc::new(Metal::new(Color::new(0.8, 0.85, 0.88), fuzz)),
+        StandardMetal::Gold => Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), fuzz)),
+        StandardMetal::Silver => Arc::new(Metal::new(Color::new(0.9, 0.9, 0.9), fuzz)),
+        StandardMetal::Copper => Arc::new(Metal::new(Color::new(0.72, 0.45, 0.2), fuzz)),
+        StandardMetal::Aluminium => Arc::new(Metal::new(Color::new(0.8, 0.85, 0.88), fuzz)),
     }
 }
 
@@ -76,11 +78,11 @@ pub enum StandardGlasses {
     Diamond,
 }
 
-pub fn create_standard_glass(palette: StandardGlasses) -> Rc<dyn Material> {
+pub fn create_standard_glass(palette: StandardGlasses) -> Arc<dyn Material> {
     match palette {
-        StandardGlasses::Water => Rc::new(Dielectric::new(1.333)),
-        StandardGlasses::Glass => Rc::new(Dielectric::new(1.5)),
-        StandardGlasses::Diamond => Rc::new(Dielectric::new(2.42)),
+        StandardGlasses::Water => Arc::new(Dielectric::new(1.333)),
+        StandardGlasses::Glass => Arc::new(Dielectric::new(1.5)),
+        StandardGlasses::Diamond => Arc::new(Dielectric::new(2.42)),
     }
 }
 
@@ -93,25 +95,56 @@ pub enum LightColor {
     Purple,
 }
 
-pub fn create_light_material(palette: LightColor) -> Rc<dyn Material> {
+pub fn create_light_material(palette: LightColor) -> Arc<dyn Material> {
     match palette {
-        LightColor::White => Rc::new(DiffuseLight::new(Color::new(7.0, 7.0, 7.0))),
-        LightColor::Yellow => Rc::new(DiffuseLight::new(Color::new(1.0, 1.0, 0.0))),
-        LightColor::Red => Rc::new(DiffuseLight::new(Color::new(1.0, 0.0, 0.0))),
-        LightColor::Green => Rc::new(DiffuseLight::new(Color::new(0.0, 1.0, 0.0))),
-        LightColor::Blue => Rc::new(DiffuseLight::new(Color::new(0.0, 0.0, 1.0))),
-        LightColor::Purple => Rc::new(DiffuseLight::new(Color::new(1.0, 0.0, 1.0))),
+        LightColor::White => Arc::new(DiffuseLight::new(Color::new(7.0, 7.0, 7.0))),
+        LightColor::Yellow => Arc::new(DiffuseLight::new(Color::new(1.0, 1.0, 0.0))),
+        LightColor::Red => Arc::new(DiffuseLight::new(Color::new(1.0, 0.0, 0.0))),
+        LightColor::Green => Arc::new(DiffuseLight::new(Color::new(0.0, 1.0, 0.0))),
+        LightColor::Blue => Arc::new(DiffuseLight::new(Color::new(0.0, 0.0, 1.0))),
+        LightColor::Purple => Arc::new(DiffuseLight::new(Color::new(1.0, 0.0, 1.0))),
     }
 }
 
-pub trait Material {
-    fn scatter(
+// A direction picked by `Material::sample`.
+pub struct BsdfSample {
+    // Direction the light arrives from, a unit vector pointing away from the surface.
+    pub wi: Vector3<f64>,
+    // BSDF times the cosine with the normal, for light arriving along `wi`.
+    pub f: Color,
+    // Density, per unit solid angle, with which `wi` was picked.
+    pub pdf: f64,
+    // `wi` comes from a delta lobe, the single exact direction of a mirror or of glass.
+    // `f` and `pdf` then both hold the probability of picking that lobe instead of a
+    // density, and only their ratio is meaningful.
+    pub delta: bool,
+}
+
+// Materials see both directions from the hit point: `wo` towards where the light leaves,
+// the reverse of the incoming ray, and `wi` towards where it arrives from. Both are unit
+// vectors.
+pub trait Material: Send + Sync {
+    // Picks the direction the light arrives from with numbers drawn from `sampler`, or
+    // None if it is absorbed.
+    fn sample(
         &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool;
+        _wo: &Vector3<f64>,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        None
+    }
+
+    // BSDF times the cosine with the normal for light arriving along `wi`. Delta lobes
+    // never contain a given direction and are left out.
+    fn eval(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Density with which `sample` picks `wi`, 0 for delta lobes.
+    fn pdf(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _rec: &HitRecord) -> f64 {
+        0.0
+    }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3<f64>) -> Color {
         Color::new(0.0, 0.0, 0.0)
@@ -126,75 +159,134 @@ impl DefaultMaterial {
     }
 }
 
-impl Material for DefaultMaterial {
-    fn scatter(
-        &self,
-        _r_in: &Ray,
-        _rec: &HitRecord,
-        _attenuation: &mut Color,
-        _scattered: &mut Ray,
-    ) -> bool {
-        false
+impl Default for DefaultMaterial {
+    fn default() -> DefaultMaterial {
+        DefaultMaterial::new()
     }
 }
 
+impl Material for DefaultMaterial {}
+
 pub struct Lambertian {
-    albedo: Color,
+    tex: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Lambertian {
-        Lambertian { albedo }
+        Lambertian::from_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>) -> Lambertian {
+        Lambertian { tex }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(
+    fn sample(
         &self,
-        _r_in: &Ray,
+        wo: &Vector3<f64>,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = rec.normal + random_unit_vector();
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let mut scatter_direction = rec.normal + sample_unit_vector(sampler.get_2d());
 
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
 
-        *scattered = Ray::new(rec.p, scatter_direction);
-        *attenuation = self.albedo;
-        true
+        let wi = scatter_direction.normalize();
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, &wi, rec),
+            pdf: self.pdf(wo, &wi, rec),
+            delta: false,
+        })
+    }
+
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, rec: &HitRecord) -> Color {
+        self.tex.value(rec.u, rec.v, &rec.p) * self.pdf(wo, wi, rec)
+    }
+
+    fn pdf(&self, _wo: &Vector3<f64>, wi: &Vector3<f64>, rec: &HitRecord) -> f64 {
+        rec.normal.dot(wi).max(0.0) / PI
     }
 }
 
 pub struct Metal {
-    albedo: Color,
+    tex: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Metal {
+        Metal::from_texture(Arc::new(SolidColor::new(albedo)), fuzz)
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>, fuzz: f64) -> Metal {
         Metal {
-            albedo,
+            tex,
             fuzz: fuzz.min(1.0),
         }
     }
+
+    // The scattered direction is the mirror direction plus a uniform random point on a
+    // sphere of radius fuzz. A direction meets that sphere at distances t = a ± s from the
+    // hit point, with a = cos(angle to the mirror direction) and s² = a² - 1 + fuzz².
+    // Converting the area density of both points to solid angle gives
+    // (a² + s²) / (2π fuzz s).
+    fn fuzz_pdf(&self, mirror: &Vector3<f64>, direction: &Vector3<f64>) -> f64 {
+        let a = direction.dot(mirror);
+        let s_squared = a * a - 1.0 + self.fuzz * self.fuzz;
+        if a <= 0.0 || s_squared <= 0.0 {
+            return 0.0;
+        }
+        (a * a + s_squared) / (2.0 * PI * self.fuzz * s_squared.sqrt())
+    }
 }
 
 impl Material for Metal {
-    fn scatter(
+    fn sample(
         &self,
-        r_in: &Ray,
+        wo: &Vector3<f64>,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut reflected = reflect(*r_in.direction(), rec.normal);
-        reflected = reflected.normalize() + (self.fuzz * random_unit_vector());
-        *scattered = Ray::new(rec.p, reflected);
-        *attenuation = self.albedo;
-        scattered.direction().dot(&rec.normal) > 0.0
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let mirror = reflect(-wo, rec.normal);
+        let albedo = self.tex.value(rec.u, rec.v, &rec.p);
+        if self.fuzz <= 0.0 {
+            return Some(BsdfSample {
+                wi: mirror,
+                f: albedo,
+                pdf: 1.0,
+                delta: true,
+            });
+        }
+
+        let reflected = mirror + (self.fuzz * sample_unit_vector(sampler.get_2d()));
+        if reflected.dot(&rec.normal) <= 0.0 {
+            return None;
+        }
+        let wi = reflected.normalize();
+        let pdf = self.fuzz_pdf(&mirror, &wi);
+        Some(BsdfSample {
+            wi,
+            f: albedo * pdf,
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, rec: &HitRecord) -> Color {
+        self.tex.value(rec.u, rec.v, &rec.p) * self.pdf(wo, wi, rec)
+    }
+
+    // A perfect mirror is a delta lobe. Fuzzy reflections that would go below the surface
+    // are absorbed, so directions below it have no density either.
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, rec: &HitRecord) -> f64 {
+        if self.fuzz <= 0.0 || wi.dot(&rec.normal) <= 0.0 {
+            return 0.0;
+        }
+        self.fuzz_pdf(&reflect(-wo, rec.normal), wi)
     }
 }
 
@@ -215,59 +307,102 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(
+    // Reflects or refracts, picking each with the fraction of light that takes it, so
+    // both lobes are delta lobes with a weight of 1.
+    fn sample(
         &self,
-        r_in: &Ray,
+        wo: &Vector3<f64>,
         rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
         let ri = if rec.front_face {
             1.0 / self.refraction_index
         } else {
             self.refraction_index
         };
 
-        let unit_direction = r_in.direction().normalize();
-        let cos_theta = (-unit_direction).dot(&rec.normal).min(1.0);
+        let unit_direction = -wo;
+        let cos_theta = wo.dot(&rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || Dielectric::reflectance(cos_theta, ri) > random_double() {
-                reflect(unit_direction, rec.normal)
-            } else {
-                refract(unit_direction, rec.normal, ri)
-            };
+        let reflectance = if cannot_refract {
+            1.0
+        } else {
+            Dielectric::reflectance(cos_theta, ri)
+        };
+        let (wi, probability) = if reflectance > sampler.get_1d() {
+            (reflect(unit_direction, rec.normal), reflectance)
+        } else {
+            (refract(unit_direction, rec.normal, ri), 1.0 - reflectance)
+        };
 
-        *scattered = Ray::new(rec.p, direction);
-        true
+        Some(BsdfSample {
+            wi,
+            f: Color::new(1.0, 1.0, 1.0) * probability,
+            pdf: probability,
+            delta: true,
+        })
     }
 }
 
 pub struct DiffuseLight {
-    emit: Color,
+    tex: Arc<dyn Texture>,
 }
 
 impl DiffuseLight {
     pub fn new(emit: Color) -> DiffuseLight {
-        DiffuseLight { emit }
+        DiffuseLight::from_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>) -> DiffuseLight {
+        DiffuseLight { tex }
     }
 }
 
+// Lights only emit: the default `sample` absorbs all the light arriving on them.
 impl Material for DiffuseLight {
-    fn scatter(
+    fn emitted(&self, u: f64, v: f64, p: &Point3<f64>) -> Color {
+        self.tex.value(u, v, p)
+    }
+}
+
+// Scatters light equally in every direction, the phase function of fog and smoke.
+pub struct Isotropic {
+    tex: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Isotropic {
+        Isotropic::from_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>) -> Isotropic {
+        Isotropic { tex }
+    }
+}
+
+impl Material for Isotropic {
+    fn sample(
         &self,
-        _r_in: &Ray,
-        _rec: &HitRecord,
-        _attenuation: &mut Color,
-        _scattered: &mut Ray,
-    ) -> bool {
-        false
+        wo: &Vector3<f64>,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let wi = sample_unit_vector(sampler.get_2d());
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, &wi, rec),
+            pdf: self.pdf(wo, &wi, rec),
+            delta: false,
+        })
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Point3<f64>) -> Color {
-        self.emit
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>, rec: &HitRecord) -> Color {
+        self.tex.value(rec.u, rec.v, &rec.p) * self.pdf(wo, wi, rec)
+    }
+
+    fn pdf(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>, _rec: &HitRecord) -> f64 {
+        1.0 / (4.0 * PI)
     }
 }
@@ -0,0 +1,317 @@
+use std::fmt;
+use std::sync::Arc;
+
+use na::{Point3, Vector3};
+
+use crate::{
+    aabb::Aabb,
+    bvh::{FlatBvh, SplitMethod},
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    triangle::{face_normal, intersect, set_shading_normal, triangle_bbox},
+};
+
+// Triangles per leaf of the mesh hierarchy.
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+#[derive(Debug)]
+pub enum MeshError {
+    IndexOutOfRange {
+        index: u32,
+        vertices: usize,
+    },
+    MaterialIdOutOfRange {
+        id: u32,
+        materials: usize,
+    },
+    // A per-vertex or per-triangle attribute list of the wrong length.
+    Count {
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::IndexOutOfRange { index, vertices } => write!(
+                f,
+                "triangle index {} out of range ({} vertices)",
+                index, vertices
+            ),
+            MeshError::MaterialIdOutOfRange { id, materials } => write!(
+                f,
+                "material id {} out of range ({} materials)",
+                id, materials
+            ),
+            MeshError::Count {
+                what,
+                expected,
+                found,
+            } => write!(f, "expected {} {}, found {}", expected, what, found),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+// Indexed triangle mesh. Vertex attributes are stored once and shared by every triangle
+// that references them, and the triangles are searched through the mesh's own flat BVH,
+// so a large model costs a single Hittable in the scene.
+pub struct TriangleMesh {
+    positions: Vec<Point3<f64>>,
+    normals: Vec<Vector3<f64>>, // empty, or one per vertex (zero where unknown)
+    uvs: Vec<(f64, f64)>,       // empty, or one per vertex
+    indices: Vec<[u32; 3]>,
+    materials: Vec<Arc<dyn Material>>,
+    material_ids: Vec<u32>, // empty when every triangle uses materials[0]
+    bvh: FlatBvh,
+    // Ids of the triangles that are not degenerate, ordered so that every leaf covers a
+    // contiguous range. Degenerate triangles are kept in `indices` but never hit.
+    triangles: Vec<u32>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3<f64>>,
+        indices: Vec<[u32; 3]>,
+        mat: Arc<dyn Material>,
+    ) -> Result<TriangleMesh, MeshError> {
+        if let Some(&index) = indices
+            .iter()
+            .flatten()
+            .find(|&&i| i as usize >= positions.len())
+        {
+            return Err(MeshError::IndexOutOfRange {
+                index,
+                vertices: positions.len(),
+            });
+        }
+
+        let vertices = |[a, b, c]: [u32; 3]| {
+            [
+                &positions[a as usize],
+                &positions[b as usize],
+                &positions[c as usize],
+            ]
+        };
+        let live: Vec<u32> = (0..indices.len() as u32)
+            .filter(|&i| {
+                let [a, b, c] = vertices(indices[i as usize]);
+                face_normal(a, b, c).is_some()
+            })
+            .collect();
+        let boxes: Vec<Aabb> = live
+            .iter()
+            .map(|&i| {
+                let [a, b, c] = vertices(indices[i as usize]);
+                triangle_bbox(a, b, c)
+            })
+            .collect();
+        let (bvh, order) = FlatBvh::build(&boxes, SplitMethod::Median, MAX_LEAF_TRIANGLES);
+        let triangles = order.into_iter().map(|i| live[i as usize]).collect();
+
+        Ok(TriangleMesh {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices,
+            materials: vec![mat],
+            material_ids: Vec::new(),
+            bvh,
+            triangles,
+        })
+    }
+
+    // Per-vertex shading normals. A zero normal falls back to the flat face normal.
+    pub fn with_normals(mut self, normals: Vec<Vector3<f64>>) -> Result<TriangleMesh, MeshError> {
+        check_count("normals", self.positions.len(), normals.len())?;
+        self.normals = normals;
+        Ok(self)
+    }
+
+    // Per-vertex texture coordinates. Without them, (u, v) are the barycentrics of the hit.
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> Result<TriangleMesh, MeshError> {
+        check_count("uvs", self.positions.len(), uvs.len())?;
+        self.uvs = uvs;
+        Ok(self)
+    }
+
+    // One material per triangle, given as an index into `materials`.
+    pub fn with_materials(
+        mut self,
+        materials: Vec<Arc<dyn Material>>,
+        material_ids: Vec<u32>,
+    ) -> Result<TriangleMesh, MeshError> {
+        check_count("material ids", self.indices.len(), material_ids.len())?;
+        if let Some(&id) = material_ids
+            .iter()
+            .find(|&&id| id as usize >= materials.len())
+        {
+            return Err(MeshError::MaterialIdOutOfRange {
+                id,
+                materials: materials.len(),
+            });
+        }
+        self.materials = materials;
+        self.material_ids = material_ids;
+        Ok(self)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn vertices(&self, triangle: usize) -> [&Point3<f64>; 3] {
+        let [a, b, c] = self.indices[triangle];
+        [
+            &self.positions[a as usize],
+            &self.positions[b as usize],
+            &self.positions[c as usize],
+        ]
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let mut closest: Option<(usize, f64, [f64; 3])> = None;
+
+        self.bvh.hit(r, ray_t, |leaf, interval| {
+            let mut closest_so_far = None;
+            for &triangle in &self.triangles[leaf] {
+                let [a, b, c] = self.vertices(triangle as usize);
+                let t_max = closest_so_far.unwrap_or(interval.max);
+                if let Some((t, bary)) = intersect(r, Interval::new(interval.min, t_max), a, b, c) {
+                    closest_so_far = Some(t);
+                    closest = Some((triangle as usize, t, bary));
+                }
+            }
+            closest_so_far
+        });
+
+        let Some((triangle, t, bary)) = closest else {
+            return false;
+        };
+
+        let ids = self.indices[triangle].map(|i| i as usize);
+        let [a, b, c] = self.vertices(triangle);
+        // Never None: degenerate triangles are left out of the hierarchy.
+        let Some(normal) = face_normal(a, b, c) else {
+            return false;
+        };
+
+        rec.t = t;
+        rec.p = Point3::from(bary[0] * a.coords + bary[1] * b.coords + bary[2] * c.coords);
+        if self.uvs.is_empty() {
+            rec.u = bary[1];
+            rec.v = bary[2];
+        } else {
+            let uv = ids.map(|i| self.uvs[i]);
+            rec.u = bary[0] * uv[0].0 + bary[1] * uv[1].0 + bary[2] * uv[2].0;
+            rec.v = bary[0] * uv[0].1 + bary[1] * uv[1].1 + bary[2] * uv[2].1;
+        }
+        rec.mat = match self.material_ids.get(triangle) {
+            Some(&id) => self.materials[id as usize].clone(),
+            None => self.materials[0].clone(),
+        };
+        rec.set_face_normal(r, normal);
+
+        if !self.normals.is_empty() {
+            let n = ids.map(|i| self.normals[i]);
+            if n.iter().all(|n| n.norm_squared() > 0.0) {
+                let interpolated = bary[0] * n[0] + bary[1] * n[1] + bary[2] * n[2];
+                set_shading_normal(rec, interpolated);
+            }
+        }
+        true
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+fn check_count(what: &'static str, expected: usize, found: usize) -> Result<(), MeshError> {
+    if found == expected {
+        Ok(())
+    } else {
+        Err(MeshError::Count {
+            what,
+            expected,
+            found,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DefaultMaterial;
+
+    fn square() -> Vec<Point3<f64>> {
+        vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn mesh(indices: Vec<[u32; 3]>) -> Result<TriangleMesh, MeshError> {
+        TriangleMesh::new(square(), indices, Arc::new(DefaultMaterial::new()))
+    }
+
+    fn hit(mesh: &TriangleMesh, x: f64, y: f64) -> Option<HitRecord> {
+        let r = Ray::new(Point3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        mesh.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let err = mesh(vec![[0, 1, 4]]).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "triangle index 4 out of range (4 vertices)"
+        );
+
+        let m = mesh(vec![[0, 1, 2]]).unwrap();
+        let err = m.with_normals(vec![Vector3::z(); 3]).err().unwrap();
+        assert_eq!(err.to_string(), "expected 4 normals, found 3");
+
+        let m = mesh(vec![[0, 1, 2]]).unwrap();
+        let err = m.with_uvs(Vec::new()).err().unwrap();
+        assert_eq!(err.to_string(), "expected 4 uvs, found 0");
+
+        let mat: Arc<dyn Material> = Arc::new(DefaultMaterial::new());
+        let m = mesh(vec![[0, 1, 2]]).unwrap();
+        let err = m
+            .with_materials(vec![mat.clone()], vec![0, 0])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "expected 1 material ids, found 2");
+
+        let m = mesh(vec![[0, 1, 2]]).unwrap();
+        let err = m.with_materials(vec![mat], vec![1]).err().unwrap();
+        assert_eq!(err.to_string(), "material id 1 out of range (1 materials)");
+    }
+
+    #[test]
+    fn never_hits_degenerate_triangles() {
+        // The second triangle is the edge from (0, 0) to (1, 1).
+        let m = mesh(vec![[0, 1, 2], [0, 2, 0], [0, 2, 3]]).unwrap();
+        assert_eq!(m.triangle_count(), 3);
+        for (x, y) in [(0.5, 0.5), (0.75, 0.25), (0.25, 0.75)] {
+            let rec = hit(&m, x, y).unwrap();
+            assert_eq!(rec.normal, Vector3::z());
+        }
+
+        let m = mesh(vec![[0, 0, 0], [1, 1, 2]]).unwrap();
+        assert!(hit(&m, 0.0, 0.0).is_none());
+        assert!(hit(&m, 1.0, 0.5).is_none());
+    }
+}
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use na::{Point3, Vector3};
+
+use crate::{
+    hittable_list::HittableList,
+    material::Material,
+    mesh::{MeshError, TriangleMesh},
+    triangle::Triangle,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Mesh(MeshError),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(err) => write!(f, "{}", err),
+            ObjError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ObjError::Mesh(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<io::Error> for ObjError {
+    fn from(err: io::Error) -> ObjError {
+        ObjError::Io(err)
+    }
+}
+
+impl From<MeshError> for ObjError {
+    fn from(err: MeshError) -> ObjError {
+        ObjError::Mesh(err)
+    }
+}
+
+// Materials of a mesh, looked up by the `usemtl` name first, then by the group (`g`) name.
+pub struct ObjMaterials {
+    pub by_name: HashMap<String, Arc<dyn Material>>,
+    pub default: Arc<dyn Material>,
+}
+
+impl ObjMaterials {
+    pub fn new(default: Arc<dyn Material>) -> ObjMaterials {
+        ObjMaterials {
+            by_name: HashMap::new(),
+            default,
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, material: Arc<dyn Material>) {
+        self.by_name.insert(name.to_string(), material);
+    }
+
+    fn lookup(&self, usemtl: Option<&str>, group: Option<&str>) -> Arc<dyn Material> {
+        usemtl
+            .and_then(|name| self.by_name.get(name))
+            .or_else(|| group.and_then(|name| self.by_name.get(name)))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+pub fn load_obj(path: &Path, materials: &ObjMaterials) -> Result<HittableList, ObjError> {
+    parse_obj(&fs::read_to_string(path)?, materials)
+}
+
+pub fn load_obj_mesh(path: &Path, materials: &ObjMaterials) -> Result<TriangleMesh, ObjError> {
+    parse_obj_mesh(&fs::read_to_string(path)?, materials)
+}
+
+// Reads an OBJ file into a list of separate triangles.
+pub fn parse_obj(source: &str, materials: &ObjMaterials) -> Result<HittableList, ObjError> {
+    let data = read_obj(source, materials)?;
+    let mut list = HittableList::new();
+
+    for face in &data.faces {
+        let [a, b, c] = face.corners;
+        let mat = data.materials[face.material].clone();
+
+        let mut triangle = Triangle::new(
+            data.positions[a.0],
+            data.positions[b.0],
+            data.positions[c.0],
+            mat,
+        );
+        if let (Some(ta), Some(tb), Some(tc)) = (a.1, b.1, c.1) {
+            triangle = triangle.with_uvs([data.uvs[ta], data.uvs[tb], data.uvs[tc]]);
+        }
+        if let (Some(na), Some(nb), Some(nc)) = (a.2, b.2, c.2) {
+            triangle =
+                triangle.with_normals([data.normals[na], data.normals[nb], data.normals[nc]]);
+        }
+        list.add(Arc::new(triangle));
+    }
+
+    Ok(list)
+}
+
+// Reads an OBJ file into a single indexed mesh. Every distinct v/vt/vn combination
+// becomes one vertex of the mesh.
+pub fn parse_obj_mesh(source: &str, materials: &ObjMaterials) -> Result<TriangleMesh, ObjError> {
+    let data = read_obj(source, materials)?;
+
+    let mut vertex_ids: HashMap<Corner, u32> = HashMap::new();
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut indices = Vec::with_capacity(data.faces.len());
+    let mut material_ids = Vec::with_capacity(data.faces.len());
+
+    for face in &data.faces {
+        indices.push(face.corners.map(|corner| {
+            *vertex_ids.entry(corner).or_insert_with(|| {
+                positions.push(data.positions[corner.0]);
+                uvs.push(corner.1.map_or((0.0, 0.0), |i| data.uvs[i]));
+                normals.push(corner.2.map_or(Vector3::zeros(), |i| data.normals[i]));
+                (positions.len() - 1) as u32
+            })
+        }));
+        material_ids.push(face.material as u32);
+    }
+
+    let has_uvs = data
+        .faces
+        .iter()
+        .flat_map(|f| f.corners)
+        .any(|c| c.1.is_some());
+    let has_normals = data
+        .faces
+        .iter()
+        .flat_map(|f| f.corners)
+        .any(|c| c.2.is_some());
+
+    let mut mesh = TriangleMesh::new(positions, indices, materials.default.clone())?
+        .with_materials(data.materials, material_ids)?;
+    if has_uvs {
+        mesh = mesh.with_uvs(uvs)?;
+    }
+    if has_normals {
+        mesh = mesh.with_normals(normals)?;
+    }
+    Ok(mesh)
+}
+
+// Zero-based (position, texture coordinate, normal) indices of a face corner.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct ObjFace {
+    corners: [Corner; 3],
+    material: usize, // index into ObjData::materials
+}
+
+struct ObjData {
+    positions: Vec<Point3<f64>>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vector3<f64>>,
+    faces: Vec<ObjFace>,
+    materials: Vec<Arc<dyn Material>>,
+}
+
+// Reads the v, vt, vn, f, g and usemtl statements of a Wavefront OBJ file. Polygons are
+// triangulated as fans and degenerate triangles dropped, and faces with a zero normal lose
+// their normals; other statements are ignored.
+fn read_obj(source: &str, materials: &ObjMaterials) -> Result<ObjData, ObjError> {
+    let mut data = ObjData {
+        positions: Vec::new(),
+        uvs: Vec::new(),
+        normals: Vec::new(),
+        faces: Vec::new(),
+        materials: vec![materials.default.clone()],
+    };
+
+    let mut group: Option<String> = None;
+    let mut usemtl: Option<String> = None;
+    let mut mat = 0;
+
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| ObjError::Parse {
+            line: index + 1,
+            message,
+        };
+
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let Some(keyword) = fields.next() else {
+            continue;
+        };
+        let fields: Vec<&str> = fields.collect();
+
+        match keyword {
+            "v" => {
+                let coords = parse_floats(&fields, 3, 4).map_err(error)?;
+                data.positions
+                    .push(Point3::new(coords[0], coords[1], coords[2]));
+            }
+            "vt" => {
+                let coords = parse_floats(&fields, 1, 3).map_err(error)?;
+                data.uvs
+                    .push((coords[0], coords.get(1).copied().unwrap_or(0.0)));
+            }
+            "vn" => {
+                let coords = parse_floats(&fields, 3, 3).map_err(error)?;
+                data.normals
+                    .push(Vector3::new(coords[0], coords[1], coords[2]));
+            }
+            "g" => {
+                group = fields.first().map(|name| name.to_string());
+                mat = data.material_index(materials.lookup(usemtl.as_deref(), group.as_deref()));
+            }
+            "usemtl" => {
+                let Some(name) = fields.first() else {
+                    return Err(error("usemtl needs a material name".to_string()));
+                };
+                usemtl = Some(name.to_string());
+                mat = data.material_index(materials.lookup(usemtl.as_deref(), group.as_deref()));
+            }
+            "f" => {
+                if fields.len() < 3 {
+                    return Err(error(format!(
+                        "a face needs at least 3 vertices, found {}",
+                        fields.len()
+                    )));
+                }
+
+                let mut corners = Vec::with_capacity(fields.len());
+                for field in &fields {
+                    corners.push(
+                        parse_corner(
+                            field,
+                            data.positions.len(),
+                            data.uvs.len(),
+                            data.normals.len(),
+                        )
+                        .map_err(error)?,
+                    );
+                }
+
+                for i in 1..corners.len() - 1 {
+                    let face = [corners[0], corners[i], corners[i + 1]];
+                    let [pa, pb, pc] = face.map(|corner| data.positions[corner.0]);
+
+                    // Degenerate triangles have no normal and can never be hit.
+                    if (pb - pa).cross(&(pc - pa)).norm_squared() == 0.0 {
+                        continue;
+                    }
+
+                    // A normal of zero length gives no direction to shade with, so a face
+                    // using one is shaded flat.
+                    let zero_normal = |corner: &Corner| {
+                        corner
+                            .2
+                            .is_some_and(|n| data.normals[n].norm_squared() == 0.0)
+                    };
+                    let face = if face.iter().any(zero_normal) {
+                        face.map(|(v, vt, _)| (v, vt, None))
+                    } else {
+                        face
+                    };
+
+                    data.faces.push(ObjFace {
+                        corners: face,
+                        material: mat,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(data)
+}
+
+impl ObjData {
+    fn material_index(&mut self, material: Arc<dyn Material>) -> usize {
+        match self
+            .materials
+            .iter()
+            .position(|m| Arc::ptr_eq(m, &material))
+        {
+            Some(index) => index,
+            None => {
+                self.materials.push(material);
+                self.materials.len() - 1
+            }
+        }
+    }
+}
+
+fn parse_floats(fields: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if fields.len() < min || fields.len() > max {
+        return Err(format!(
+            "expected {} to {} numbers, found {}",
+            min,
+            max,
+            fields.len()
+        ));
+    }
+    fields
+        .iter()
+        .map(|field| match field.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(number),
+            _ => Err(format!("expected a number, found '{}'", field)),
+        })
+        .collect()
+}
+
+// Parses one face corner `v`, `v/vt`, `v//vn` or `v/vt/vn` into zero-based indices.
+fn parse_corner(
+    field: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = field.split('/');
+    let v = resolve_index(parts.next().unwrap_or(""), positions, "vertex")?;
+    let vt = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, uvs, "texture coordinate")?),
+    };
+    let vn = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, normals, "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("invalid face vertex '{}'", field));
+    }
+    Ok((v, vt, vn))
+}
+
+// OBJ indices start at 1; negative indices count back from the last element read so far.
+fn resolve_index(index: &str, count: usize, kind: &str) -> Result<usize, String> {
+    let value: i64 = index
+        .parse()
+        .map_err(|_| format!("invalid {} index '{}'", kind, index))?;
+
+    let resolved = match value {
+        0 => None,
+        v if v > 0 => Some(v as usize - 1),
+        v => count.checked_sub(v.unsigned_abs() as usize),
+    };
+
+    match resolved {
+        Some(i) if i < count => Ok(i),
+        _ => Err(format!(
+            "{} index {} out of range ({} defined)",
+            kind, value, count
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{HitRecord, Hittable},
+        interval::Interval,
+        material::DefaultMaterial,
+        ray::Ray,
+    };
+
+    fn materials() -> ObjMaterials {
+        ObjMaterials::new(Arc::new(DefaultMaterial::new()))
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        match parse_obj(source, &materials()) {
+            Err(ObjError::Parse { line, message }) => (line, message),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("parsed without error"),
+        }
+    }
+
+    // Normal at the hit of a ray going down the z axis through (x, y).
+    fn normal_at(object: &dyn Hittable, x: f64, y: f64) -> Vector3<f64> {
+        let r = Ray::new(Point3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        assert!(object.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        rec.normal
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn resolves_negative_indices() {
+        let source = format!("{}f -4 -3 -2 -1\n", SQUARE);
+        let list = parse_obj(&source, &materials()).unwrap();
+        assert_eq!(list.objects.len(), 2);
+        let mesh = parse_obj_mesh(&source, &materials()).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+
+        assert_eq!(
+            error(&format!("{}f -5 -3 -2\n", SQUARE)),
+            (5, "vertex index -5 out of range (4 defined)".to_string())
+        );
+        assert_eq!(
+            error("v 0 0 0\nf 1 1 0\n"),
+            (2, "vertex index 0 out of range (1 defined)".to_string())
+        );
+    }
+
+    #[test]
+    fn drops_degenerate_faces() {
+        // The first fan triangle of the pentagon has its vertices on one line.
+        let source = format!("{}v 1 0.5 0\nf 2 5 3 4 1\nf 1 1 2\n", SQUARE);
+        let list = parse_obj(&source, &materials()).unwrap();
+        assert_eq!(list.objects.len(), 2);
+        let mesh = parse_obj_mesh(&source, &materials()).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+    }
+
+    #[test]
+    fn shades_faces_with_zero_normals_flat() {
+        let source = format!(
+            "{}vn 0 0 0\nvn 0.6 0 0.8\nf 1//1 2//1 3//1\nf 1//2 3//2 4//1\n",
+            SQUARE
+        );
+        let list = parse_obj(&source, &materials()).unwrap();
+        let mesh = parse_obj_mesh(&source, &materials()).unwrap();
+        for object in [&list as &dyn Hittable, &mesh] {
+            assert_eq!(normal_at(object, 0.75, 0.25), Vector3::z());
+            assert_eq!(normal_at(object, 0.25, 0.75), Vector3::z());
+        }
+
+        // Valid normals are kept.
+        let source = format!("{}vn 0.6 0 0.8\nf 1//1 2//1 3//1\n", SQUARE);
+        let list = parse_obj(&source, &materials()).unwrap();
+        let n = normal_at(&list, 0.75, 0.25);
+        assert!((n - Vector3::new(0.6, 0.0, 0.8)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        assert_eq!(
+            error("v 1 x 2\n"),
+            (1, "expected a number, found 'x'".to_string())
+        );
+        assert_eq!(
+            error("# comment\nvn 1 0\n"),
+            (2, "expected 3 to 3 numbers, found 2".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2\n", SQUARE)),
+            (5, "a face needs at least 3 vertices, found 2".to_string())
+        );
+        assert_eq!(
+            error(&format!("{}f 1/1 2 3\n", SQUARE)),
+            (
+                5,
+                "texture coordinate index 1 out of range (0 defined)".to_string()
+            )
+        );
+        assert_eq!(
+            error(&format!("{}f 1/2/3/4 2 3\n", SQUARE)),
+            (
+                5,
+                "texture coordinate index 2 out of range (0 defined)".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use na::Vector3;
+
+// Orthonormal basis whose w axis is a given direction, to turn directions sampled around
+// the z axis into directions around any vector.
+pub struct Onb {
+    axis: [Vector3<f64>; 3],
+}
+
+impl Onb {
+    pub fn new(n: &Vector3<f64>) -> Onb {
+        let w = n.normalize();
+        let a = if w.x.abs() > 0.9 {
+            Vector3::y()
+        } else {
+            Vector3::x()
+        };
+        let v = w.cross(&a).normalize();
+        let u = w.cross(&v);
+        Onb { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> &Vector3<f64> {
+        &self.axis[0]
+    }
+
+    pub fn v(&self) -> &Vector3<f64> {
+        &self.axis[1]
+    }
+
+    pub fn w(&self) -> &Vector3<f64> {
+        &self.axis[2]
+    }
+
+    // From coordinates in the basis to world space.
+    pub fn transform(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        v.x * self.axis[0] + v.y * self.axis[1] + v.z * self.axis[2]
+    }
+}
@@ -0,0 +1,377 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::color::{write_color, Transfer};
+use crate::framebuffer::Framebuffer;
+use crate::interval::Interval;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Pfm,
+    Hdr,
+}
+
+impl ImageFormat {
+    // Guesses the format from the file extension, case insensitively.
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        ImageFormat::from_name(path.extension()?.to_str()?)
+    }
+
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+pub struct OutputOptions {
+    pub format: Option<ImageFormat>, // None picks the format from the file extension
+    pub bit_depth: BitDepth,
+    pub transfer: Transfer,
+}
+
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        OutputOptions {
+            format: None,
+            bit_depth: BitDepth::Eight,
+            transfer: Transfer::Gamma2,
+        }
+    }
+}
+
+// Writes the image to a file, in the format given by the options or the file extension.
+pub fn save(path: &Path, image: &Framebuffer, options: &OutputOptions) -> io::Result<()> {
+    let format = match options.format.or_else(|| ImageFormat::from_path(path)) {
+        Some(format) => format,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown image format for {}", path.display()),
+            ))
+        }
+    };
+
+    let mut out = BufWriter::new(File::create(path)?);
+    write_image(&mut out, image, format, options)?;
+    out.flush()
+}
+
+pub fn write_image(
+    out: &mut impl Write,
+    image: &Framebuffer,
+    format: ImageFormat,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::Ppm => write_ppm(out, image),
+        ImageFormat::Png => write_png(out, image, options.bit_depth, options.transfer),
+        ImageFormat::Pfm => write_pfm(out, image),
+        ImageFormat::Hdr => write_hdr(out, image),
+    }
+}
+
+// Writes the image as an ASCII P3 PPM, gamma corrected and quantized to bytes.
+pub fn write_ppm(out: &mut impl Write, image: &Framebuffer) -> io::Result<()> {
+    write!(out, "P3\n{} {}\n255\n", image.width, image.height)?;
+    for pixel_color in &image.pixels {
+        write_color(out, pixel_color)?;
+    }
+    Ok(())
+}
+
+// Writes the image as an RGB PNG with 8 or 16 bits per channel.
+pub fn write_png(
+    out: &mut impl Write,
+    image: &Framebuffer,
+    bit_depth: BitDepth,
+    transfer: Transfer,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(out, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    match transfer {
+        Transfer::Gamma2 => encoder.set_source_gamma(png::ScaledFloat::new(0.5)),
+        Transfer::Srgb => encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual),
+    }
+
+    let intensity = Interval::new(0.0, 1.0);
+    let channels = image
+        .pixels
+        .iter()
+        .flat_map(|color| color.iter().map(|&c| intensity.clamp(transfer.encode(c))));
+
+    let data: Vec<u8> = match bit_depth {
+        BitDepth::Eight => {
+            encoder.set_depth(png::BitDepth::Eight);
+            channels.map(|c| (c * 255.0).round() as u8).collect()
+        }
+        BitDepth::Sixteen => {
+            encoder.set_depth(png::BitDepth::Sixteen);
+            channels
+                .flat_map(|c| ((c * 65535.0).round() as u16).to_be_bytes())
+                .collect()
+        }
+    };
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+// Writes the linear, unclamped image as a little-endian color PFM (rows stored bottom to top).
+pub fn write_pfm(out: &mut impl Write, image: &Framebuffer) -> io::Result<()> {
+    write!(out, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    for row in image.pixels.chunks(image.width).rev() {
+        for color in row {
+            for c in color.iter() {
+                out.write_all(&(*c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Writes the linear, unclamped image as a run-length encoded Radiance RGBE file.
+pub fn write_hdr(out: &mut impl Write, image: &Framebuffer) -> io::Result<()> {
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        image.height, image.width
+    )?;
+
+    let mut scanline = vec![[0u8; 4]; image.width];
+    for row in image.pixels.chunks(image.width) {
+        for (rgbe, color) in scanline.iter_mut().zip(row) {
+            *rgbe = to_rgbe(color.x, color.y, color.z);
+        }
+
+        // The run-length scheme can only describe scanlines between 8 and 32767 pixels wide.
+        if !(8..0x8000).contains(&image.width) {
+            for rgbe in &scanline {
+                out.write_all(rgbe)?;
+            }
+            continue;
+        }
+
+        out.write_all(&[2, 2, (image.width >> 8) as u8, (image.width & 0xff) as u8])?;
+        for channel in 0..4 {
+            let bytes: Vec<u8> = scanline.iter().map(|rgbe| rgbe[channel]).collect();
+            write_rle_channel(out, &bytes)?;
+        }
+    }
+    Ok(())
+}
+
+// Shared-exponent encoding: three 8 bit mantissas and the exponent of the largest component.
+fn to_rgbe(r: f64, g: f64, b: f64) -> [u8; 4] {
+    let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+    let v = r.max(g).max(b);
+    // Infinite pixels (NaN is already dropped by max) have no exponent, so they are skipped.
+    if v < 1e-32 || !v.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // The exponent byte holds -128..=127; brighter pixels saturate at the largest value.
+    let exponent = (v.log2().floor() as i32 + 1).clamp(-128, 127);
+    let scale = 256.0 / 2f64.powi(exponent);
+    [
+        (r * scale).min(255.0) as u8,
+        (g * scale).min(255.0) as u8,
+        (b * scale).min(255.0) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+// Encodes one channel of a scanline as runs (count > 128) and literal dumps (count <= 128).
+fn write_rle_channel(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    const MIN_RUN: usize = 4;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        // Find the next run long enough to be worth encoding.
+        let mut run_start = i;
+        let mut run_len = 0;
+        while run_start < bytes.len() {
+            run_len = 1;
+            while run_start + run_len < bytes.len()
+                && run_len < 127
+                && bytes[run_start + run_len] == bytes[run_start]
+            {
+                run_len += 1;
+            }
+            if run_len >= MIN_RUN {
+                break;
+            }
+            run_start += run_len;
+        }
+
+        // Dump the literal bytes preceding the run.
+        while i < run_start {
+            let count = (run_start - i).min(128);
+            out.write_all(&[count as u8])?;
+            out.write_all(&bytes[i..i + count])?;
+            i += count;
+        }
+
+        if run_len >= MIN_RUN {
+            out.write_all(&[128 + run_len as u8, bytes[run_start]])?;
+            i += run_len;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn from_rgbe(rgbe: [u8; 4]) -> [f64; 3] {
+        if rgbe[3] == 0 {
+            return [0.0; 3];
+        }
+        let scale = 2f64.powi(rgbe[3] as i32 - 128) / 256.0;
+        [0, 1, 2].map(|c| (rgbe[c] as f64 + 0.5) * scale)
+    }
+
+    // Reads one run-length encoded scanline back into RGBE pixels.
+    fn read_rle_scanline(bytes: &mut &[u8], width: usize) -> Vec<[u8; 4]> {
+        assert_eq!(bytes[..4], [2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+        *bytes = &bytes[4..];
+        let mut scanline = vec![[0u8; 4]; width];
+        for channel in 0..4 {
+            let mut i = 0;
+            while i < width {
+                let count = bytes[0] as usize;
+                if count > 128 {
+                    for pixel in &mut scanline[i..i + count - 128] {
+                        pixel[channel] = bytes[1];
+                    }
+                    i += count - 128;
+                    *bytes = &bytes[2..];
+                } else {
+                    assert!(count > 0, "empty literal dump");
+                    for (pixel, &byte) in scanline[i..i + count].iter_mut().zip(&bytes[1..]) {
+                        pixel[channel] = byte;
+                    }
+                    i += count;
+                    *bytes = &bytes[1 + count..];
+                }
+            }
+            assert_eq!(i, width, "channel {} overruns the scanline", channel);
+        }
+        scanline
+    }
+
+    #[test]
+    fn rgbe_round_trip() {
+        for color in [
+            [0.5, 0.25, 0.125],
+            [1.0, 1.0, 1.0],
+            [3.7, 0.01, 2.0],
+            [1e-30, 5e-31, 0.0],
+            [1e6, 2e5, 3.0],
+            [1e30, 1e29, 1e28],
+        ] {
+            let decoded = from_rgbe(to_rgbe(color[0], color[1], color[2]));
+            let max = color.iter().cloned().fold(0.0, f64::max);
+            for c in 0..3 {
+                let error = (decoded[c] - color[c]).abs();
+                assert!(error <= max / 128.0, "{:?} decoded as {:?}", color, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn rgbe_handles_extreme_values() {
+        assert_eq!(to_rgbe(0.0, 0.0, 0.0), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(1e-40, 1e-40, 1e-40), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(-1.0, -2.0, -3.0), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(f64::INFINITY, 1.0, 1.0), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(f64::NAN, 0.0, 0.0), [0, 0, 0, 0]);
+
+        // Values past the largest exponent saturate instead of wrapping around.
+        for v in [2f64.powi(127), 1e300, f64::MAX] {
+            let rgbe = to_rgbe(v, 0.0, 0.0);
+            assert_eq!(rgbe, [255, 0, 0, 255], "{}", v);
+        }
+    }
+
+    #[test]
+    fn hdr_scanlines_round_trip() {
+        for width in [8, 9, 127, 128, 129, 300, 1000, 32767] {
+            let mut image = Framebuffer::new(width, 2);
+            for i in 0..width {
+                // Runs of equal pixels mixed with changing ones.
+                let v = if (i / 5) % 3 == 0 {
+                    1.0
+                } else {
+                    i as f64 * 0.37
+                };
+                image.set(i, 0, Color::new(v, 0.5, (i % 7) as f64));
+                image.set(i, 1, Color::new(0.25, 0.25, 0.25));
+            }
+
+            let mut out = Vec::new();
+            write_hdr(&mut out, &image).unwrap();
+            let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X {}\n", width);
+            assert!(out.starts_with(header.as_bytes()));
+
+            let mut bytes = &out[header.len()..];
+            for j in 0..2 {
+                let scanline = read_rle_scanline(&mut bytes, width);
+                for (i, rgbe) in scanline.iter().enumerate() {
+                    let color = image.get(i, j);
+                    assert_eq!(*rgbe, to_rgbe(color.x, color.y, color.z), "width {}", width);
+                }
+            }
+            assert!(bytes.is_empty(), "width {}: trailing bytes", width);
+        }
+    }
+
+    #[test]
+    fn hdr_writes_flat_scanlines_outside_the_rle_range() {
+        for width in [1, 7, 32768] {
+            let mut image = Framebuffer::new(width, 1);
+            image.set(0, 0, Color::new(1.0, 0.5, 0.25));
+            let mut out = Vec::new();
+            write_hdr(&mut out, &image).unwrap();
+            let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X {}\n", width);
+            assert_eq!(out.len(), header.len() + 4 * width);
+            assert_eq!(out[header.len()..header.len() + 4], [128, 64, 32, 129]);
+        }
+    }
+
+    #[test]
+    fn pfm_is_little_endian_and_bottom_up() {
+        let mut image = Framebuffer::new(2, 2);
+        image.set(0, 0, Color::new(1.0, 2.0, 3.0));
+        image.set(1, 1, Color::new(-1.5, 0.0, 1e6));
+
+        let mut out = Vec::new();
+        write_pfm(&mut out, &image).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert!(out.starts_with(header));
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(floats.len(), 12);
+        // The bottom row comes first.
+        assert_eq!(floats[3..6], [-1.5, 0.0, 1e6]);
+        assert_eq!(floats[6..9], [1.0, 2.0, 3.0]);
+    }
+}
@@ -0,0 +1,90 @@
+use na::{Point3, Vector3};
+
+use crate::{
+    hash::{hash, hash_float},
+    vec3::sample_unit_vector,
+};
+
+const POINT_COUNT: usize = 256;
+
+// Perlin gradient noise: random unit gradients on the integer lattice, blended with a
+// smoothed trilinear interpolation. The lattice is hashed from its seed rather than drawn
+// from a generator, so a texture looks the same in every render whatever the sampling
+// seed, and on every platform and version of rand.
+pub struct Perlin {
+    gradients: Vec<Vector3<f64>>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let gradients = (0..POINT_COUNT as u64)
+            .map(|i| {
+                let u = hash_float(hash(&[seed, 0, i, 0]));
+                let v = hash_float(hash(&[seed, 0, i, 1]));
+                sample_unit_vector((u, v))
+            })
+            .collect();
+        // Fisher-Yates shuffle of 0..POINT_COUNT, one for each axis.
+        let permutation = |axis: u64| {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            for i in (1..POINT_COUNT).rev() {
+                let j = hash(&[seed, axis, i as u64]) % (i as u64 + 1);
+                perm.swap(i, j as usize);
+            }
+            perm
+        };
+
+        Perlin {
+            perm_x: permutation(1),
+            perm_y: permutation(2),
+            perm_z: permutation(3),
+            gradients,
+        }
+    }
+
+    // Noise at p, roughly in [-1, 1] and 0 on the lattice points.
+    pub fn noise(&self, p: &Point3<f64>) -> f64 {
+        let cell = p.map(f64::floor);
+        let offset = p - cell;
+        // Hermite smoothing removes the grid artifacts of plain trilinear interpolation.
+        let smooth = offset.map(|t| t * t * (3.0 - 2.0 * t));
+
+        let mut accum = 0.0;
+        for corner in 0..8 {
+            let d = Vector3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let index = |axis: usize, perm: &[usize]| {
+                perm[((cell[axis] as i64 + d[axis] as i64) & (POINT_COUNT as i64 - 1)) as usize]
+            };
+            let gradient = self.gradients
+                [index(0, &self.perm_x) ^ index(1, &self.perm_y) ^ index(2, &self.perm_z)];
+
+            let weight = Vector3::from_fn(|axis, _| {
+                if d[axis] == 1 {
+                    smooth[axis]
+                } else {
+                    1.0 - smooth[axis]
+                }
+            });
+            let to_p = offset - d.cast::<f64>();
+            accum += weight.product() * gradient.dot(&to_p);
+        }
+        accum
+    }
+
+    // Fractal sum of `octaves` layers of noise, each at twice the frequency and half the
+    // weight of the one before. Always positive.
+    pub fn turbulence(&self, p: &Point3<f64>, octaves: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(&p);
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        accum.abs()
+    }
+}
@@ -1,8 +1,8 @@
 use na::{Point3, Vector3};
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::{
-    aabb::Aabb, hittable::{HitRecord, Hittable}, hittable_list::HittableList, interval::Interval, material::Material, ray::Ray
+    aabb::Aabb, hittable::{HitRecord, Hittable}, hittable_list::HittableList, interval::Interval, material::Material, ray::Ray, sampler::Sampler
 };
 
 pub struct Quad {
@@ -10,14 +10,15 @@ pub struct Quad {
     u: Vector3<f64>,
     v: Vector3<f64>,
     w: Vector3<f64>,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
     bbox: Aabb,
     normal: Vector3<f64>,
     d: f64,
+    area: f64,
 }
 
 impl Quad {
-    pub fn new(q: Point3<f64>, u: Vector3<f64>, v: Vector3<f64>, mat: Rc<dyn Material>) -> Quad {
+    pub fn new(q: Point3<f64>, u: Vector3<f64>, v: Vector3<f64>, mat: Arc<dyn Material>) -> Quad {
         let n = u.cross(&v);
         let normal = n.normalize();
         let d = normal.dot(&q.coords);
@@ -32,6 +33,7 @@ impl Quad {
             bbox: Aabb::new(),
             normal,
             d,
+            area: n.norm(),
         };
 
         quad.set_bounding_box();
@@ -95,10 +97,30 @@ impl Hittable for Quad {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    // Points are sampled uniformly over the area, so the density per unit solid angle is
+    // distance² / (cosine * area).
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return 0.0;
+        }
+
+        let length = r.direction().norm();
+        let distance_squared = rec.t * rec.t * length * length;
+        let cosine = (r.direction().dot(&self.normal) / length).abs();
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3<f64>, _time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let (a, b) = sampler.get_2d();
+        let p = self.q + a * self.u + b * self.v;
+        p - origin
+    }
 }
 
 
-pub fn box_(a: Point3<f64>, b: Point3<f64>, mat: Rc<dyn Material>) -> Rc<dyn Hittable> {
+pub fn box_(a: Point3<f64>, b: Point3<f64>, mat: Arc<dyn Material>) -> Arc<dyn Hittable> {
     let mut sides = HittableList::new();
 
     let min = Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
@@ -108,12 +130,12 @@ pub fn box_(a: Point3<f64>, b: Point3<f64>, mat: Rc<dyn Material>) -> Rc<dyn Hit
     let dy = Vector3::new(0.0, max.y - min.y, 0.0);
     let dz = Vector3::new(0.0, 0.0, max.z - min.z);
 
-    sides.add(Rc::new(Quad::new(Point3::new(min.x, min.y, max.z), dx, dy, mat.clone()))); // front
-    sides.add(Rc::new(Quad::new(Point3::new(max.x, min.y, max.z), -dz, dy, mat.clone()))); // right
-    sides.add(Rc::new(Quad::new(Point3::new(max.x, min.y, min.z), -dx, dy, mat.clone()))); // back
-    sides.add(Rc::new(Quad::new(Point3::new(min.x, min.y, min.z), dz, dy, mat.clone()))); // left
-    sides.add(Rc::new(Quad::new(Point3::new(min.x, max.y, max.z), dx, -dz, mat.clone()))); // top
-    sides.add(Rc::new(Quad::new(Point3::new(min.x, min.y, min.z), dx, dz, mat.clone()))); // bottom
+    sides.add(Arc::new(Quad::new(Point3::new(min.x, min.y, max.z), dx, dy, mat.clone()))); // front
+    sides.add(Arc::new(Quad::new(Point3::new(max.x, min.y, max.z), -dz, dy, mat.clone()))); // right
+    sides.add(Arc::new(Quad::new(Point3::new(max.x, min.y, min.z), -dx, dy, mat.clone()))); // back
+    sides.add(Arc::new(Quad::new(Point3::new(min.x, min.y, min.z), dz, dy, mat.clone()))); // left
+    sides.add(Arc::new(Quad::new(Point3::new(min.x, max.y, max.z), dx, -dz, mat.clone()))); // top
+    sides.add(Arc::new(Quad::new(Point3::new(min.x, min.y, min.z), dx, dz, mat.clone()))); // bottom
 
-    Rc::new(sides)
+    Arc::new(sides)
 }
\ No newline at end of file
@@ -4,11 +4,29 @@ use nalgebra as na;
 pub struct Ray {
     pub orig: Point3<f64>,
     pub dir: Vector3<f64>,
+    pub tm: f64, // instant the ray is traced at, for moving objects
+    // Number in [0, 1) drawn by the camera's sampler for objects whose hits are random,
+    // like volumes. Rays moved into another space must keep it.
+    pub sample: f64,
 }
 
 impl Ray {
     pub fn new(orig: Point3<f64>, dir: Vector3<f64>) -> Ray {
-        Ray { orig, dir }
+        Ray::new_with_time(orig, dir, 0.0)
+    }
+
+    pub fn new_with_time(orig: Point3<f64>, dir: Vector3<f64>, tm: f64) -> Ray {
+        Ray {
+            orig,
+            dir,
+            tm,
+            sample: 0.5,
+        }
+    }
+
+    pub fn with_sample(mut self, sample: f64) -> Ray {
+        self.sample = sample;
+        self
     }
 
     pub fn origin(&self) -> &Point3<f64> {
@@ -19,6 +37,14 @@ impl Ray {
         &self.dir
     }
 
+    pub fn time(&self) -> f64 {
+        self.tm
+    }
+
+    pub fn sample(&self) -> f64 {
+        self.sample
+    }
+
     pub fn at(&self, t: f64) -> Point3<f64> {
         self.orig + t * self.dir
     }
@@ -0,0 +1,462 @@
+use crate::{
+    hash::{hash, hash_float, mix_bits},
+    random_double,
+};
+
+// Source of the random numbers of the camera samples. Each sample of a pixel asks for
+// its numbers in the same order (pixel offset, lens, time, then a few per bounce), and
+// every request is a new dimension. Samplers that know the pixel, the sample index and
+// the dimension can spread the samples of a pixel much more evenly than white noise.
+pub trait Sampler {
+    // Starts sample `index` of pixel (i, j), going back to the first dimension.
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize);
+
+    // Number in [0, 1) for the next dimension.
+    fn get_1d(&mut self) -> f64;
+
+    // Point of the unit square for the next two dimensions.
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name.to_ascii_lowercase().as_str() {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+
+    // `samples_per_pixel` sets the number of strata of the stratified sampler and the
+    // precision of the Halton one, and `seed` the scrambling of all but the independent
+    // one.
+    pub fn create(&self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new()),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+// White noise from the random generator of the thread, whatever the pixel and sample.
+pub struct IndependentSampler;
+
+impl IndependentSampler {
+    pub fn new() -> IndependentSampler {
+        IndependentSampler
+    }
+}
+
+impl Default for IndependentSampler {
+    fn default() -> IndependentSampler {
+        IndependentSampler::new()
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, _i: usize, _j: usize, _index: usize) {}
+
+    fn get_1d(&mut self) -> f64 {
+        random_double()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (random_double(), random_double())
+    }
+}
+
+// Jittered sampling: every dimension is cut into samples_per_pixel strata (a grid of
+// about sqrt(samples_per_pixel)² cells in 2D), and the samples of a pixel visit them in a
+// random order, each at a random point of its stratum. Samples past samples_per_pixel
+// start another round of the strata.
+pub struct StratifiedSampler {
+    samples_per_pixel: u64,
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> StratifiedSampler {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.clamp(1, u32::MAX as usize) as u64,
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Stratum, among `strata`, of the current sample in the current dimension.
+    fn stratum(&self, strata: u64) -> u64 {
+        let round = self.index / self.samples_per_pixel;
+        let position = self.index % self.samples_per_pixel;
+        let shuffle = hash(&[self.pixel, self.dimension, round]);
+        permutation_element(position as u32, strata as u32, shuffle as u32) as u64
+    }
+
+    fn jitter(&self, axis: u64) -> f64 {
+        hash_float(hash(&[self.pixel, self.dimension, self.index, axis]))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.pixel = hash(&[self.seed, i as u64, j as u64]);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let strata = self.samples_per_pixel;
+        let x = (self.stratum(strata) as f64 + self.jitter(0)) / strata as f64;
+        self.dimension += 1;
+        x
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let side = (self.samples_per_pixel as f64).sqrt().ceil() as u64;
+        let stratum = self.stratum(side * side);
+        let x = ((stratum % side) as f64 + self.jitter(0)) / side as f64;
+        let y = ((stratum / side) as f64 + self.jitter(1)) / side as f64;
+        self.dimension += 1;
+        (x, y)
+    }
+}
+
+// Halton sequence: dimension d is the radical inverse of the sample index in the d-th
+// prime base, with the digits randomly permuted for each pixel and dimension. Dimensions
+// past the prime table fall back to white noise.
+pub struct HaltonSampler {
+    primes: Vec<u64>,
+    resolution: f64, // Smallest digit weight computed exactly, 1 / samples_per_pixel
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    const DIMENSIONS: usize = 256;
+
+    pub fn new(samples_per_pixel: usize, seed: u64) -> HaltonSampler {
+        let mut primes = Vec::with_capacity(HaltonSampler::DIMENSIONS);
+        let mut candidate = 2;
+        while primes.len() < HaltonSampler::DIMENSIONS {
+            if primes.iter().all(|&p| candidate % p != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+
+        HaltonSampler {
+            primes,
+            resolution: 1.0 / samples_per_pixel.max(1) as f64,
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.pixel = hash(&[self.seed, i as u64, j as u64]);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension as u64;
+        self.dimension += 1;
+        match self.primes.get(dimension as usize) {
+            Some(&base) => scrambled_radical_inverse(
+                base,
+                self.index,
+                hash(&[self.pixel, dimension]),
+                self.resolution,
+            ),
+            None => hash_float(hash(&[self.pixel, dimension, self.index])),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Owen-scrambled Sobol points, following Burley's "Practical Hash-based Owen Scrambling"
+// (2020). Every request takes the first one or two Sobol dimensions, which are very well
+// stratified, and decorrelates them from the other requests by shuffling the sample
+// indices, with a different nested uniform scramble per pixel and dimension.
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Scrambled index and scrambling seeds of the current dimension.
+    fn next_dimension(&mut self) -> (u32, u64) {
+        let seed = hash(&[self.pixel, self.dimension]);
+        self.dimension += 1;
+        (nested_uniform_scramble(self.index, seed as u32), seed)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.pixel = hash(&[self.seed, i as u64, j as u64]);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, seed) = self.next_dimension();
+        let x = nested_uniform_scramble(sobol(index, 0), (seed >> 32) as u32);
+        to_unit_float(x)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, seed) = self.next_dimension();
+        let x = nested_uniform_scramble(sobol(index, 0), (seed >> 32) as u32);
+        let y = nested_uniform_scramble(sobol(index, 1), hash(&[seed]) as u32);
+        (to_unit_float(x), to_unit_float(y))
+    }
+}
+
+// Direction numbers of the first two Sobol dimensions: the van der Corput sequence, and
+// the primitive polynomial x + 1.
+static SOBOL_DIRECTIONS: [[u32; 32]; 2] = sobol_directions();
+
+const fn sobol_directions() -> [[u32; 32]; 2] {
+    let mut directions = [[0; 32]; 2];
+    let mut m: u32 = 1;
+    let mut k = 0;
+    while k < 32 {
+        directions[0][k] = 1 << (31 - k);
+        directions[1][k] = m << (31 - k);
+        m ^= m << 1;
+        k += 1;
+    }
+    directions
+}
+
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let directions = &SOBOL_DIRECTIONS[dimension];
+    let mut x = 0;
+    while index != 0 {
+        x ^= directions[index.trailing_zeros() as usize];
+        index &= index - 1;
+    }
+    x
+}
+
+// Owen scrambling of the bits of `x`: each bit is flipped depending on the seed and on
+// all the bits above it, which keeps the stratification of Sobol points.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn to_unit_float(x: u32) -> f64 {
+    x as f64 / 4_294_967_296.0
+}
+
+// Radical inverse of `index` in `base`, each digit position going through its own
+// random permutation of the digits. Positions past the last digit of the index hold a
+// permuted 0 too. They are computed down to `resolution`, which keeps the samples of a
+// pixel stratified, and the rest is a uniform jitter in the last cell.
+fn scrambled_radical_inverse(base: u64, mut index: u64, seed: u64, resolution: f64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut scale = inv_base;
+    let mut result = 0.0;
+    let mut position = 0;
+    while index > 0 || scale >= resolution {
+        let digit = index % base;
+        let shuffle = mix_bits(seed ^ position);
+        let permuted = permutation_element(digit as u32, base as u32, shuffle as u32);
+        result += permuted as f64 * scale;
+        index /= base;
+        scale *= inv_base;
+        position += 1;
+    }
+    result += hash_float(mix_bits(seed ^ position)) * scale * base as f64;
+    result.min(1.0 - f64::EPSILON / 2.0)
+}
+
+// Element `i` of a random permutation of 0..len chosen by `seed`, without building the
+// permutation (Kensler, "Correlated Multi-Jittered Sampling", 2013).
+fn permutation_element(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            return i.wrapping_add(seed) % len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    // The first 12 requests of samples 0..count of pixel (i, j), alternating
+    // between get_1d and get_2d as the camera does.
+    fn draw(sampler: &mut dyn Sampler, (i, j): (usize, usize), count: usize) -> Vec<Vec<f64>> {
+        (0..count)
+            .map(|index| {
+                sampler.start_pixel_sample(i, j, index);
+                let mut values = Vec::new();
+                for request in 0..12 {
+                    if request % 2 == 0 {
+                        values.push(sampler.get_1d());
+                    } else {
+                        let (x, y) = sampler.get_2d();
+                        values.extend([x, y]);
+                    }
+                }
+                values
+            })
+            .collect()
+    }
+
+    // Whether the `cells` equal cells of [0, 1) hold the same number of values.
+    fn evenly_filled(values: impl Iterator<Item = usize>, cells: usize) -> bool {
+        let mut counts = vec![0; cells];
+        for cell in values {
+            counts[cell] += 1;
+        }
+        counts.iter().all(|&c| c == counts[0])
+    }
+
+    #[test]
+    fn values_are_in_the_unit_interval() {
+        for kind in KINDS {
+            let mut sampler = kind.create(16, 5);
+            for pixel in [(0, 0), (7, 3), (1000, 2000)] {
+                for values in draw(&mut *sampler, pixel, 300) {
+                    assert!(values.iter().all(|v| (0.0..1.0).contains(v)), "{:?}", kind);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_of_a_pixel_are_stratified() {
+        const PRIMES: [usize; 18] = [
+            2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61,
+        ];
+        let n = 16;
+        let cell = |v: f64, cells: usize| (v * cells as f64) as usize;
+
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let mut sampler = kind.create(n, 9);
+            let samples = draw(&mut *sampler, (4, 2), n);
+
+            // Numbers 0, 3, 6... come from get_1d, the others in pairs from get_2d.
+            for dimension in 0..samples[0].len() {
+                let (count, cells) = match kind {
+                    // A 2D request is a 4x4 grid, each of its axes only 4 strata.
+                    SamplerKind::Stratified if dimension % 3 != 0 => (n, 4),
+                    // The first power of each base that fits in the samples, none for
+                    // the bases above their number.
+                    SamplerKind::Halton => {
+                        let base = PRIMES[dimension];
+                        let mut cells = if base <= n { base } else { 1 };
+                        while cells * base <= n {
+                            cells *= base;
+                        }
+                        (cells, cells)
+                    }
+                    _ => (n, n),
+                };
+                let values = samples
+                    .iter()
+                    .take(count)
+                    .map(|s| cell(s[dimension], cells));
+                assert!(
+                    evenly_filled(values, cells),
+                    "{:?}, dimension {}",
+                    kind,
+                    dimension
+                );
+            }
+
+            // Every 2D request covers the 4x4 grid, one sample per cell.
+            if kind != SamplerKind::Halton {
+                for x in (1..samples[0].len()).step_by(3) {
+                    let cells = samples
+                        .iter()
+                        .map(|s| cell(s[x], 4) * 4 + cell(s[x + 1], 4));
+                    assert!(evenly_filled(cells, n), "{:?}, dimension {}", kind, x);
+                }
+            }
+        }
+    }
+}
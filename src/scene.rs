@@ -0,0 +1,991 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use na::{Point3, Vector3};
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    constant_medium::ConstantMedium,
+    cylinder::Cylinder,
+    hittable::{Hittable, Translate},
+    hittable_list::HittableList,
+    material::{
+        create_light_material, create_standard_glass, create_standard_material,
+        create_standard_metal, Dielectric, DiffuseLight, Isotropic, Lambertian, LightColor,
+        Material, Metal, StandardColor, StandardGlasses, StandardMetal,
+    },
+    quad::{box_, Quad},
+    sampler::SamplerKind,
+    sphere::Sphere,
+    texture::{
+        CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, SolidColor, Texture,
+        TextureFilter, TextureWrap, UvCheckerTexture,
+    },
+    transform::Transform,
+};
+
+/************************************************/
+// Scene files describe one statement per line:
+//
+//   # Comments start with a hash
+//   camera width=800 aspect=4:3 spp=100 lookfrom=378,278,-800 lookat=378,278,0
+//   texture floor checker scale=50 even=0.2,0.3,0.1 odd=0.9,0.9,0.9
+//   material red lambertian color=0.65,0.05,0.05
+//   material ground lambertian texture=floor
+//   material gold metal preset=gold fuzz=0.3
+//   material smoke isotropic color=0.9,0.9,0.9
+//   sphere center=200,100,300 radius=100 material=gold
+//   box min=0,0,0 max=100,100,100 material=red rotate_y=45 translate=300,0,300
+//   sphere center=400,100,200 radius=80 material=smoke density=0.01 move=0,20,0
+//
+// Camera keys set the image (width, aspect, spp, adaptive, min_spp, sampler), the paths
+// (depth, roulette_depth), the view (vfov, lookfrom, lookat, vup, defocus_angle,
+// focus_dist, shutter) and the look (background, brightness). Textures are solid,
+// checker, uv_checker, turbulence, marble, wood or image, and must come before the
+// materials that use them. Shapes accept move, density (all but quads, to fill them
+// with a volume) and the transforms (translate, rotate_x, rotate_y, rotate_z, rotate,
+// scale), which are applied in the order they are written; rotations are counterclockwise
+// around their axis. doc.md lists every key of every statement.
+/************************************************/
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: HittableList,
+    pub lights: HittableList, // emissive spheres, quads and boxes, to give to Camera::render
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(err: io::Error) -> SceneError {
+        SceneError::Io(err)
+    }
+}
+
+// Image files named in the scene are found relative to the scene file.
+pub fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    parse(&fs::read_to_string(path)?, dir)
+}
+
+// Image files named in the scene are found relative to the current directory.
+pub fn parse_scene(source: &str) -> Result<Scene, SceneError> {
+    parse(source, Path::new(""))
+}
+
+fn parse(source: &str, dir: &Path) -> Result<Scene, SceneError> {
+    let mut parser = Parser {
+        dir: dir.to_path_buf(),
+        camera: Camera::new(16.0 / 9.0, 400),
+        world: HittableList::new(),
+        lights: HittableList::new(),
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+    };
+
+    for (index, line) in source.lines().enumerate() {
+        let tokens = tokenize(line);
+        if tokens.is_empty() {
+            continue;
+        }
+        parser
+            .statement(&tokens)
+            .map_err(|(column, message)| SceneError::Parse {
+                line: index + 1,
+                column,
+                message,
+            })?;
+    }
+
+    Ok(Scene {
+        camera: parser.camera,
+        world: parser.world,
+        lights: parser.lights,
+    })
+}
+
+// Errors are reported as (column, message) and given their line number by parse_scene.
+type ParseResult<T> = Result<T, (usize, String)>;
+
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+// Splits a line into whitespace separated tokens, dropping everything after a '#'.
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let line = line.split('#').next().unwrap_or("");
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in line.char_indices().chain([(line.len(), ' ')]) {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                tokens.push(Token {
+                    text: &line[s..i],
+                    column: line[..s].chars().count() + 1,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+struct Attribute<'a> {
+    key: &'a str,
+    value: &'a str,
+    column: usize,       // column of the key
+    value_column: usize, // column of the value
+    used: bool,
+}
+
+struct Attributes<'a> {
+    list: Vec<Attribute<'a>>,
+    column: usize, // column of the statement keyword, for missing attributes
+}
+
+impl<'a> Attributes<'a> {
+    fn new(tokens: &[Token<'a>], column: usize) -> ParseResult<Attributes<'a>> {
+        let mut list: Vec<Attribute<'a>> = Vec::new();
+        for token in tokens {
+            let Some((key, value)) = token.text.split_once('=') else {
+                return Err((
+                    token.column,
+                    format!("expected key=value, found '{}'", token.text),
+                ));
+            };
+            if key.is_empty() || value.is_empty() {
+                return Err((
+                    token.column,
+                    format!("expected key=value, found '{}'", token.text),
+                ));
+            }
+            if !is_transform(key) && list.iter().any(|a| a.key == key) {
+                return Err((token.column, format!("duplicate attribute '{}'", key)));
+            }
+            list.push(Attribute {
+                key,
+                value,
+                column: token.column,
+                value_column: token.column + key.chars().count() + 1,
+                used: false,
+            });
+        }
+        Ok(Attributes { list, column })
+    }
+
+    fn take(&mut self, key: &str) -> Option<(&'a str, usize)> {
+        let attribute = self.list.iter_mut().find(|a| a.key == key && !a.used)?;
+        attribute.used = true;
+        Some((attribute.value, attribute.value_column))
+    }
+
+    fn required(&mut self, key: &str) -> ParseResult<(&'a str, usize)> {
+        self.take(key)
+            .ok_or_else(|| (self.column, format!("missing attribute '{}'", key)))
+    }
+
+    fn number(&mut self, key: &str) -> ParseResult<Option<f64>> {
+        self.take(key).map(|(v, c)| parse_number(v, c)).transpose()
+    }
+
+    fn required_number(&mut self, key: &str) -> ParseResult<f64> {
+        let (value, column) = self.required(key)?;
+        parse_number(value, column)
+    }
+
+    fn integer(&mut self, key: &str) -> ParseResult<Option<usize>> {
+        self.take(key)
+            .map(|(value, column)| {
+                value.parse::<usize>().map_err(|_| {
+                    (
+                        column,
+                        format!("expected a non-negative integer, found '{}'", value),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    // An integer that must be at least 1, like a width or a number of samples.
+    fn positive_integer(&mut self, key: &str) -> ParseResult<Option<usize>> {
+        self.take(key)
+            .map(|(value, column)| match value.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err((
+                    column,
+                    format!("expected a positive integer, found '{}'", value),
+                )),
+            })
+            .transpose()
+    }
+
+    fn positive_number(&mut self, key: &str) -> ParseResult<Option<f64>> {
+        self.take(key)
+            .map(|(value, column)| {
+                let number = parse_number(value, column)?;
+                if number <= 0.0 {
+                    return Err((column, format!("{} must be positive", key)));
+                }
+                Ok(number)
+            })
+            .transpose()
+    }
+
+    fn vector(&mut self, key: &str) -> ParseResult<Option<Vector3<f64>>> {
+        self.take(key).map(|(v, c)| parse_vector(v, c)).transpose()
+    }
+
+    fn required_vector(&mut self, key: &str) -> ParseResult<Vector3<f64>> {
+        let (value, column) = self.required(key)?;
+        parse_vector(value, column)
+    }
+
+    fn required_point(&mut self, key: &str) -> ParseResult<Point3<f64>> {
+        Ok(Point3::from(self.required_vector(key)?))
+    }
+
+    // Fails on the first attribute that the statement did not consume.
+    fn finish(&self) -> ParseResult<()> {
+        match self.list.iter().find(|a| !a.used) {
+            Some(a) => Err((a.column, format!("unknown attribute '{}'", a.key))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_transform(key: &str) -> bool {
+    matches!(
+        key,
+        "translate" | "rotate_x" | "rotate_y" | "rotate_z" | "rotate" | "scale"
+    )
+}
+
+fn parse_number(value: &str, column: usize) -> ParseResult<f64> {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err((column, format!("expected a number, found '{}'", value))),
+    }
+}
+
+fn parse_vector(value: &str, column: usize) -> ParseResult<Vector3<f64>> {
+    let mut coords = [0.0; 3];
+    let mut count = 0;
+    let mut offset = 0;
+
+    for part in value.split(',') {
+        if count == 3 {
+            return Err((column, format!("expected 3 components, found '{}'", value)));
+        }
+        coords[count] = parse_number(part, column + offset)?;
+        count += 1;
+        offset += part.chars().count() + 1;
+    }
+    if count != 3 {
+        return Err((column, format!("expected 3 components, found '{}'", value)));
+    }
+    Ok(Vector3::new(coords[0], coords[1], coords[2]))
+}
+
+// `X,Y,Z,DEGREES`: an axis followed by an angle.
+fn parse_rotation(value: &str, column: usize) -> ParseResult<(Vector3<f64>, f64)> {
+    let Some((axis, angle)) = value.rsplit_once(',') else {
+        return Err((column, format!("expected X,Y,Z,DEGREES, found '{}'", value)));
+    };
+    let axis_vector = parse_vector(axis, column)?;
+    if axis_vector == Vector3::zeros() {
+        return Err((column, "the rotation axis must not be zero".to_string()));
+    }
+    let angle = parse_number(angle, column + axis.chars().count() + 1)?;
+    Ok((axis_vector, angle))
+}
+
+// Either one factor for every axis, or X,Y,Z.
+fn parse_scale(value: &str, column: usize) -> ParseResult<Vector3<f64>> {
+    let factors = if value.contains(',') {
+        parse_vector(value, column)?
+    } else {
+        Vector3::repeat(parse_number(value, column)?)
+    };
+    if factors.iter().any(|&f| f == 0.0) {
+        return Err((column, "scale factors must not be zero".to_string()));
+    }
+    Ok(factors)
+}
+
+// `OPEN,CLOSE`: the time interval rays are spread over.
+fn parse_shutter(value: &str, column: usize) -> ParseResult<(f64, f64)> {
+    let Some((open_value, close_value)) = value.split_once(',') else {
+        return Err((column, format!("expected OPEN,CLOSE, found '{}'", value)));
+    };
+    let open = parse_number(open_value, column)?;
+    let close = parse_number(close_value, column + open_value.chars().count() + 1)?;
+    if close < open {
+        return Err((column, "the shutter must close after it opens".to_string()));
+    }
+    Ok((open, close))
+}
+
+fn parse_aspect_ratio(value: &str, column: usize) -> ParseResult<f64> {
+    let ratio = match value.split_once([':', '/']) {
+        Some((w, h)) => parse_number(w, column)? / parse_number(h, column + w.chars().count() + 1)?,
+        None => parse_number(value, column)?,
+    };
+    if !(ratio.is_finite() && ratio > 0.0) {
+        return Err((
+            column,
+            format!("aspect ratio must be positive, found '{}'", value),
+        ));
+    }
+    Ok(ratio)
+}
+
+// The camera looks from lookfrom toward lookat, with vup giving the up direction of the
+// image, so the two points must differ and vup must not be parallel to the view.
+fn check_view(cam: &Camera, column: usize) -> ParseResult<()> {
+    let view = cam.lookat - cam.lookfrom;
+    if view == Vector3::zeros() {
+        return Err((
+            column,
+            "lookfrom and lookat must be different points".to_string(),
+        ));
+    }
+    if cam.vup.cross(&view).norm() <= 1e-9 * cam.vup.norm() * view.norm() {
+        return Err((
+            column,
+            "vup must not be zero or parallel to the view direction".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+struct Parser {
+    dir: PathBuf, // base for relative file names
+    camera: Camera,
+    world: HittableList,
+    lights: HittableList,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, (Arc<dyn Material>, bool)>, // and whether it emits light
+}
+
+impl Parser {
+    fn statement(&mut self, tokens: &[Token]) -> ParseResult<()> {
+        let keyword = &tokens[0];
+        match keyword.text {
+            "camera" => {
+                let mut attributes = Attributes::new(&tokens[1..], keyword.column)?;
+                self.camera_settings(&mut attributes)?;
+                attributes.finish()
+            }
+            "texture" => self.texture(tokens),
+            "material" => self.material(tokens),
+            "sphere" | "quad" | "box" | "cylinder" => {
+                let mut attributes = Attributes::new(&tokens[1..], keyword.column)?;
+                let (object, is_light) = self.shape(keyword.text, &mut attributes)?;
+                let object = Self::transforms(object, &mut attributes)?;
+                attributes.finish()?;
+                if is_light {
+                    self.lights.add(object.clone());
+                }
+                self.world.add(object);
+                Ok(())
+            }
+            other => Err((keyword.column, format!("unknown statement '{}'", other))),
+        }
+    }
+
+    fn camera_settings(&mut self, attributes: &mut Attributes) -> ParseResult<()> {
+        let cam = &mut self.camera;
+        if let Some(width) = attributes.positive_integer("width")? {
+            cam.img_width = width;
+        }
+        if let Some((value, column)) = attributes.take("aspect") {
+            cam.aspect_ratio = parse_aspect_ratio(value, column)?;
+        }
+        if let Some(spp) = attributes.positive_integer("spp")? {
+            cam.samples_per_pixel = spp;
+        }
+        if let Some(threshold) = attributes.positive_number("adaptive")? {
+            cam.adaptive_threshold = threshold;
+        }
+        if let Some(min_spp) = attributes.positive_integer("min_spp")? {
+            cam.min_samples_per_pixel = min_spp;
+        }
+        if let Some((value, column)) = attributes.take("sampler") {
+            cam.sampler = SamplerKind::from_name(value)
+                .ok_or_else(|| (column, format!("unknown sampler '{}'", value)))?;
+        }
+        if let Some(depth) = attributes.positive_integer("depth")? {
+            cam.max_depth = depth;
+        }
+        if let Some(roulette_depth) = attributes.integer("roulette_depth")? {
+            cam.roulette_depth = roulette_depth;
+        }
+        if let Some((value, column)) = attributes.take("vfov") {
+            let vfov = parse_number(value, column)?;
+            if !(vfov > 0.0 && vfov < 180.0) {
+                return Err((column, "vfov must be between 0 and 180 degrees".to_string()));
+            }
+            cam.vfov = vfov;
+        }
+        let mut view_column = None; // column of the last view attribute, for errors
+        if let Some((value, column)) = attributes.take("lookfrom") {
+            cam.lookfrom = Point3::from(parse_vector(value, column)?);
+            view_column = Some(column);
+        }
+        if let Some((value, column)) = attributes.take("lookat") {
+            cam.lookat = Point3::from(parse_vector(value, column)?);
+            view_column = Some(column);
+        }
+        if let Some((value, column)) = attributes.take("vup") {
+            cam.vup = parse_vector(value, column)?;
+            view_column = Some(column);
+        }
+        if let Some(column) = view_column {
+            check_view(cam, column)?;
+        }
+        if let Some(defocus_angle) = attributes.number("defocus_angle")? {
+            cam.defocus_angle = defocus_angle;
+        }
+        if let Some(focus_dist) = attributes.positive_number("focus_dist")? {
+            cam.focus_dist = focus_dist;
+        }
+        if let Some((value, column)) = attributes.take("shutter") {
+            (cam.shutter_open, cam.shutter_close) = parse_shutter(value, column)?;
+        }
+        if let Some(background) = attributes.vector("background")? {
+            cam.background = background;
+        }
+        if let Some(brightness) = attributes.positive_number("brightness")? {
+            cam.brightness = brightness;
+        }
+        Ok(())
+    }
+
+    // texture <name> <kind> attributes...
+    fn texture(&mut self, tokens: &[Token]) -> ParseResult<()> {
+        let (name, kind) = definition(tokens, |name| self.textures.contains_key(name))?;
+
+        let mut attributes = Attributes::new(&tokens[3..], kind.column)?;
+        let texture: Arc<dyn Texture> = match kind.text {
+            "solid" => Arc::new(SolidColor::new(attributes.required_vector("color")?)),
+            "checker" => {
+                let scale = attributes.required_number("scale")?;
+                if scale <= 0.0 {
+                    return Err((kind.column, "checker scale must be positive".to_string()));
+                }
+                let even = self.texture_ref(&mut attributes, "even")?;
+                let odd = self.texture_ref(&mut attributes, "odd")?;
+                Arc::new(CheckerTexture::new(scale, even, odd))
+            }
+            "uv_checker" => {
+                let columns = attributes.positive_integer("columns")?.unwrap_or(8);
+                let rows = attributes.positive_integer("rows")?.unwrap_or(8);
+                let even = self.texture_ref(&mut attributes, "even")?;
+                let odd = self.texture_ref(&mut attributes, "odd")?;
+                Arc::new(UvCheckerTexture::new(columns, rows, even, odd))
+            }
+            "turbulence" | "marble" | "wood" => {
+                let pattern = match kind.text {
+                    "turbulence" => NoisePattern::Turbulence,
+                    "marble" => NoisePattern::Marble,
+                    _ => NoisePattern::Wood,
+                };
+                let mut texture = NoiseTexture::new(pattern, attributes.required_number("scale")?);
+                if let Some(octaves) = attributes.positive_integer("octaves")? {
+                    texture = texture.with_octaves(octaves);
+                }
+                if let Some(seed) = attributes.integer("seed")? {
+                    texture = texture.with_seed(seed as u64);
+                }
+                let low = self.texture_or(&mut attributes, "low", Color::new(0.0, 0.0, 0.0))?;
+                let high = self.texture_or(&mut attributes, "high", Color::new(1.0, 1.0, 1.0))?;
+                Arc::new(texture.with_textures(low, high))
+            }
+            "image" => {
+                let (file, column) = attributes.required("file")?;
+                let mut texture = ImageTexture::load(&self.dir.join(file))
+                    .map_err(|err| (column, format!("cannot load '{}': {}", file, err)))?;
+                if let Some((value, column)) = attributes.take("filter") {
+                    texture = texture.with_filter(texture_filter(value, column)?);
+                }
+                if let Some((value, column)) = attributes.take("wrap") {
+                    texture = texture.with_wrap(texture_wrap(value, column)?);
+                }
+                Arc::new(texture)
+            }
+            other => {
+                return Err((
+                    kind.column,
+                    format!(
+                        "unknown texture kind '{}', expected solid, checker, uv_checker, \
+                         turbulence, marble, wood or image",
+                        other
+                    ),
+                ))
+            }
+        };
+        attributes.finish()?;
+
+        self.textures.insert(name.text.to_string(), texture);
+        Ok(())
+    }
+
+    // An attribute holding either a color or the name of a texture defined earlier.
+    fn texture_ref(&self, attributes: &mut Attributes, key: &str) -> ParseResult<Arc<dyn Texture>> {
+        let (value, column) = attributes.required(key)?;
+        self.texture_value(value, column)
+    }
+
+    // Like texture_ref, with a color to use when the attribute is missing.
+    fn texture_or(
+        &self,
+        attributes: &mut Attributes,
+        key: &str,
+        default: Color,
+    ) -> ParseResult<Arc<dyn Texture>> {
+        match attributes.take(key) {
+            Some((value, column)) => self.texture_value(value, column),
+            None => Ok(Arc::new(SolidColor::new(default))),
+        }
+    }
+
+    fn texture_value(&self, value: &str, column: usize) -> ParseResult<Arc<dyn Texture>> {
+        if let Some(texture) = self.textures.get(value) {
+            return Ok(texture.clone());
+        }
+        match parse_vector(value, column) {
+            Ok(color) => Ok(Arc::new(SolidColor::new(color))),
+            Err(_) => Err((
+                column,
+                format!("expected a color or a texture name, found '{}'", value),
+            )),
+        }
+    }
+
+    // The color of a lambertian, metal or light material: either color=R,G,B or
+    // texture=<name>.
+    fn surface_texture(&self, attributes: &mut Attributes) -> ParseResult<Arc<dyn Texture>> {
+        match (attributes.take("color"), attributes.take("texture")) {
+            (Some(_), Some((_, column))) => Err((
+                column,
+                "a material takes either a color or a texture, not both".to_string(),
+            )),
+            (Some((value, column)), None) => {
+                Ok(Arc::new(SolidColor::new(parse_vector(value, column)?)))
+            }
+            (None, Some((name, column))) => self
+                .textures
+                .get(name)
+                .cloned()
+                .ok_or_else(|| (column, format!("undefined texture '{}'", name))),
+            (None, None) => Err((attributes.column, "missing attribute 'color'".to_string())),
+        }
+    }
+
+    // material <name> <kind> attributes...
+    fn material(&mut self, tokens: &[Token]) -> ParseResult<()> {
+        let (name, kind) = definition(tokens, |name| self.materials.contains_key(name))?;
+
+        let mut attributes = Attributes::new(&tokens[3..], kind.column)?;
+        let preset = attributes.take("preset");
+
+        let material: Arc<dyn Material> = match (kind.text, preset) {
+            ("lambertian", Some((preset, column))) => {
+                create_standard_material(standard_color(preset, column)?)
+            }
+            ("lambertian", None) => Arc::new(Lambertian::from_texture(
+                self.surface_texture(&mut attributes)?,
+            )),
+            ("metal", Some((preset, column))) => {
+                let fuzz = attributes.number("fuzz")?.unwrap_or(0.0);
+                create_standard_metal(standard_metal(preset, column)?, fuzz)
+            }
+            ("metal", None) => {
+                let albedo = self.surface_texture(&mut attributes)?;
+                let fuzz = attributes.number("fuzz")?.unwrap_or(0.0);
+                Arc::new(Metal::from_texture(albedo, fuzz))
+            }
+            ("dielectric", Some((preset, column))) => {
+                create_standard_glass(standard_glass(preset, column)?)
+            }
+            ("dielectric", None) => Arc::new(Dielectric::new(attributes.required_number("ior")?)),
+            ("light", Some((preset, column))) => {
+                create_light_material(light_color(preset, column)?)
+            }
+            ("light", None) => Arc::new(DiffuseLight::from_texture(
+                self.surface_texture(&mut attributes)?,
+            )),
+            ("isotropic", _) => Arc::new(Isotropic::from_texture(
+                self.surface_texture(&mut attributes)?,
+            )),
+            (other, _) => {
+                return Err((
+                    kind.column,
+                    format!(
+                        "unknown material kind '{}', expected lambertian, metal, dielectric, \
+                     light or isotropic",
+                        other
+                    ),
+                ))
+            }
+        };
+        attributes.finish()?;
+
+        let emissive = kind.text == "light";
+        self.materials
+            .insert(name.text.to_string(), (material, emissive));
+        Ok(())
+    }
+
+    // The material and whether it emits light.
+    fn material_ref(&self, attributes: &mut Attributes) -> ParseResult<(Arc<dyn Material>, bool)> {
+        let (name, column) = attributes.required("material")?;
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| (column, format!("undefined material '{}'", name)))
+    }
+
+    // The shape and whether the camera should sample it as a light. Cylinders can't be
+    // sampled, so light only comes from them when rays happen to hit them.
+    fn shape(
+        &self,
+        kind: &str,
+        attributes: &mut Attributes,
+    ) -> ParseResult<(Arc<dyn Hittable>, bool)> {
+        let (mat, emissive) = self.material_ref(attributes)?;
+        let object: Arc<dyn Hittable> = match kind {
+            "sphere" => {
+                let center = attributes.required_point("center")?;
+                let radius = attributes.required_number("radius")?;
+                match attributes.vector("center2")? {
+                    Some(center2) => Arc::new(Sphere::new_moving(
+                        center,
+                        Point3::from(center2),
+                        radius,
+                        mat.clone(),
+                    )),
+                    None => Arc::new(Sphere::new(center, radius, mat.clone())),
+                }
+            }
+            "quad" => {
+                let q = attributes.required_point("q")?;
+                let u = attributes.required_vector("u")?;
+                let v = attributes.required_vector("v")?;
+                Arc::new(Quad::new(q, u, v, mat.clone()))
+            }
+            "box" => {
+                let min = attributes.required_point("min")?;
+                let max = attributes.required_point("max")?;
+                box_(min, max, mat.clone())
+            }
+            _ => {
+                let base = attributes.required_point("base")?;
+                let height = attributes.required_number("height")?;
+                let radius = attributes.required_number("radius")?;
+                Arc::new(Cylinder::new(base, height, radius, mat.clone()))
+            }
+        };
+
+        // With a density, the shape is only the boundary of a volume scattering light
+        // with its material.
+        let Some((value, column)) = attributes.take("density") else {
+            return Ok((object, emissive && kind != "cylinder"));
+        };
+        let density = parse_number(value, column)?;
+        if density <= 0.0 {
+            return Err((column, "density must be positive".to_string()));
+        }
+        if kind == "quad" {
+            return Err((column, "a quad cannot hold a volume".to_string()));
+        }
+        let medium = ConstantMedium::from_material(object, density, mat);
+        Ok((Arc::new(medium), false))
+    }
+
+    // Places the object with every transform attribute, applied in the order they are
+    // written and combined into a single Transform, then sets it in motion with `move`.
+    fn transforms(
+        object: Arc<dyn Hittable>,
+        attributes: &mut Attributes,
+    ) -> ParseResult<Arc<dyn Hittable>> {
+        let mut transform: Option<Transform> = None;
+        let mut column = 0;
+        for a in attributes.list.iter_mut().filter(|a| is_transform(a.key)) {
+            a.used = true;
+            column = a.column;
+            let current = match transform {
+                Some(transform) => transform,
+                None => Transform::identity(object.clone()),
+            };
+            transform = Some(match a.key {
+                "translate" => current.translate(parse_vector(a.value, a.value_column)?),
+                "rotate_x" => current.rotate_x(parse_number(a.value, a.value_column)?),
+                "rotate_y" => current.rotate_y(parse_number(a.value, a.value_column)?),
+                "rotate_z" => current.rotate_z(parse_number(a.value, a.value_column)?),
+                "rotate" => {
+                    let (axis, angle) = parse_rotation(a.value, a.value_column)?;
+                    current.rotate(axis, angle)
+                }
+                _ => current.scale(parse_scale(a.value, a.value_column)?),
+            });
+        }
+        let object: Arc<dyn Hittable> = match transform {
+            // A transform that cannot be inverted is reported at its last step.
+            Some(transform) => Arc::new(
+                transform
+                    .into_instance()
+                    .map_err(|err| (column, err.to_string()))?,
+            ),
+            None => object,
+        };
+
+        // Motion applies to the placed object, wherever the attribute is written.
+        Ok(match attributes.vector("move")? {
+            Some(motion) => Arc::new(Translate::new_moving(object, Vector3::zeros(), motion)),
+            None => object,
+        })
+    }
+}
+
+// Name and kind of a `<keyword> <name> <kind>` definition, checking that the name is
+// valid and not already taken.
+fn definition<'a, 'b>(
+    tokens: &'b [Token<'a>],
+    defined: impl Fn(&str) -> bool,
+) -> ParseResult<(&'b Token<'a>, &'b Token<'a>)> {
+    let keyword = &tokens[0];
+    let (Some(name), Some(kind)) = (tokens.get(1), tokens.get(2)) else {
+        return Err((
+            keyword.column,
+            format!("expected '{} <name> <kind>'", keyword.text),
+        ));
+    };
+    if name.text.contains('=') {
+        return Err((
+            name.column,
+            format!("invalid {} name '{}'", keyword.text, name.text),
+        ));
+    }
+    if defined(name.text) {
+        return Err((
+            name.column,
+            format!("{} '{}' is already defined", keyword.text, name.text),
+        ));
+    }
+    Ok((name, kind))
+}
+
+fn texture_filter(name: &str, column: usize) -> ParseResult<TextureFilter> {
+    match name {
+        "nearest" => Ok(TextureFilter::Nearest),
+        "bilinear" => Ok(TextureFilter::Bilinear),
+        _ => Err((column, format!("unknown texture filter '{}'", name))),
+    }
+}
+
+fn texture_wrap(name: &str, column: usize) -> ParseResult<TextureWrap> {
+    match name {
+        "repeat" => Ok(TextureWrap::Repeat),
+        "clamp" => Ok(TextureWrap::Clamp),
+        "mirror" => Ok(TextureWrap::Mirror),
+        _ => Err((column, format!("unknown texture wrap mode '{}'", name))),
+    }
+}
+
+fn standard_color(name: &str, column: usize) -> ParseResult<StandardColor> {
+    match name {
+        "red" => Ok(StandardColor::Red),
+        "green" => Ok(StandardColor::Green),
+        "blue" => Ok(StandardColor::Blue),
+        "white" => Ok(StandardColor::White),
+        "black" => Ok(StandardColor::Black),
+        "grey" => Ok(StandardColor::Grey),
+        "yellow" => Ok(StandardColor::Yellow),
+        "purple" => Ok(StandardColor::Purple),
+        _ => Err((column, format!("unknown color preset '{}'", name))),
+    }
+}
+
+fn standard_metal(name: &str, column: usize) -> ParseResult<StandardMetal> {
+    match name {
+        "gold" => Ok(StandardMetal::Gold),
+        "silver" => Ok(StandardMetal::Silver),
+        "copper" => Ok(StandardMetal::Copper),
+        "aluminium" => Ok(StandardMetal::Aluminium),
+        _ => Err((column, format!("unknown metal preset '{}'", name))),
+    }
+}
+
+fn standard_glass(name: &str, column: usize) -> ParseResult<StandardGlasses> {
+    match name {
+        "water" => Ok(StandardGlasses::Water),
+        "glass" => Ok(StandardGlasses::Glass),
+        "diamond" => Ok(StandardGlasses::Diamond),
+        _ => Err((column, format!("unknown glass preset '{}'", name))),
+    }
+}
+
+fn light_color(name: &str, column: usize) -> ParseResult<LightColor> {
+    match name {
+        "white" => Ok(LightColor::White),
+        "yellow" => Ok(LightColor::Yellow),
+        "red" => Ok(LightColor::Red),
+        "green" => Ok(LightColor::Green),
+        "blue" => Ok(LightColor::Blue),
+        "purple" => Ok(LightColor::Purple),
+        _ => Err((column, format!("unknown light preset '{}'", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Line, column and message of the error of `source`.
+    fn error(source: &str) -> (usize, usize, String) {
+        match parse_scene(source) {
+            Err(SceneError::Parse {
+                line,
+                column,
+                message,
+            }) => (line, column, message),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("'{}' parsed without error", source),
+        }
+    }
+
+    #[test]
+    fn parses_a_scene() {
+        let scene = parse_scene(
+            "# Comment\n\
+             camera width=40 aspect=4:3 spp=2 lookfrom=0,0,5 lookat=0,0,0 vup=0,1,0\n\
+             material red lambertian color=0.65,0.05,0.05\n\
+             material lamp light color=4,4,4\n\
+             sphere center=0,0,0 radius=1 material=red rotate=0,1,0,30 scale=2\n\
+             quad q=0,2,0 u=1,0,0 v=0,0,1 material=lamp  # a light\n",
+        )
+        .unwrap();
+        assert_eq!(scene.camera.img_width, 40);
+        assert_eq!(scene.camera.samples_per_pixel, 2);
+        assert_eq!(scene.world.objects.len(), 2);
+        assert_eq!(scene.lights.objects.len(), 1);
+    }
+
+    #[test]
+    fn reports_lines_and_columns() {
+        assert_eq!(
+            error("\nfoo bar"),
+            (2, 1, "unknown statement 'foo'".to_string())
+        );
+        assert_eq!(
+            error("sphere center=0,0,0 radius=1 material=nope"),
+            (1, 39, "undefined material 'nope'".to_string())
+        );
+        assert_eq!(
+            error("material red lambertian color=1,x,1"),
+            (1, 33, "expected a number, found 'x'".to_string())
+        );
+        assert_eq!(
+            error("material red lambertian color=1,1,1 color=1,1,1"),
+            (1, 37, "duplicate attribute 'color'".to_string())
+        );
+        assert_eq!(
+            error("material red lambertian color=1,1,1 shine=2"),
+            (1, 37, "unknown attribute 'shine'".to_string())
+        );
+        assert_eq!(
+            error("material red lambertian color=1,0,0\nsphere center=0,0,0 material=red"),
+            (2, 1, "missing attribute 'radius'".to_string())
+        );
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        // 'é' is two bytes but one column.
+        assert_eq!(error("material rougé lambertian color=1,1,x").1, 37);
+        assert_eq!(error("camera shutter=0,x").1, 18);
+        assert_eq!(error("camera aspect=4:x").1, 17);
+    }
+
+    #[test]
+    fn rejects_bad_camera_values() {
+        for (source, column) in [
+            ("camera width=0", 14),
+            ("camera spp=0", 12),
+            ("camera min_spp=0", 16),
+            ("camera depth=0", 14),
+            ("camera adaptive=0", 17),
+            ("camera vfov=0", 13),
+            ("camera vfov=180", 13),
+            ("camera focus_dist=0", 19),
+            ("camera brightness=-1", 19),
+            ("camera lookfrom=1,2,3 lookat=1,2,3", 30),
+            ("camera lookfrom=0,0,0 lookat=0,1,0", 30),
+            ("camera lookat=0,0,-1 vup=0,0,0", 26),
+        ] {
+            assert_eq!(error(source).1, column, "{}", source);
+        }
+        assert_eq!(
+            error("texture t uv_checker columns=0 even=0,0,0 odd=1,1,1").1,
+            30
+        );
+    }
+
+    #[test]
+    fn rejects_bad_transforms() {
+        let material = "material red lambertian color=1,0,0\n";
+        for (shape, column, message) in [
+            (
+                "sphere center=0,0,0 radius=1 material=red rotate=0,0,0,30",
+                50,
+                "the rotation axis must not be zero",
+            ),
+            (
+                "sphere center=0,0,0 radius=1 material=red scale=1,0,1",
+                49,
+                "scale factors must not be zero",
+            ),
+            (
+                "sphere center=0,0,0 radius=1 material=red scale=1e-200 translate=1,0,0 scale=1e-200",
+                72,
+                "transform is not invertible",
+            ),
+        ] {
+            let (line, error_column, text) = error(&format!("{}{}", material, shape));
+            assert_eq!((line, error_column, text.as_str()), (2, column, message));
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use std::rc::Rc;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 use na::Vector3;
 use nalgebra::Point3;
@@ -8,34 +9,59 @@ use crate::{
     hittable::{HitRecord, Hittable},
     interval::Interval,
     material::Material,
+    onb::Onb,
     ray::Ray,
+    sampler::Sampler,
+    vec3::sample_unit_vector,
 };
 
 pub struct Sphere {
     center: Point3<f64>,
+    velocity: Vector3<f64>, // distance covered between time 0 and time 1
     radius: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
     bbox: Aabb,
 }
 
 impl Sphere {
-    pub fn new(center: Point3<f64>, radius: f64, mat: Rc<dyn Material>) -> Sphere {
+    pub fn new(center: Point3<f64>, radius: f64, mat: Arc<dyn Material>) -> Sphere {
         let rvec = Vector3::new(radius, radius, radius);
         let mut bbox = Aabb::new();
-        bbox.aabb(&(&center - &rvec), &(&center + &rvec));
+        bbox.aabb(&(center - rvec), &(center + rvec));
 
         Sphere {
             center,
+            velocity: Vector3::zeros(),
             radius: radius.max(0.0),
             mat,
             bbox,
         }
     }
+
+    // Sphere moving in a straight line from `center1` at time 0 to `center2` at time 1.
+    // It stays still before and after.
+    pub fn new_moving(
+        center1: Point3<f64>,
+        center2: Point3<f64>,
+        radius: f64,
+        mat: Arc<dyn Material>,
+    ) -> Sphere {
+        let mut sphere = Sphere::new(center1, radius, mat);
+        let end = Sphere::new(center2, radius, sphere.mat.clone());
+        sphere.velocity = center2 - center1;
+        sphere.bbox = Aabb::aabb_from_boxes(&sphere.bbox, &end.bbox);
+        sphere
+    }
+
+    fn center_at(&self, time: f64) -> Point3<f64> {
+        self.center + time.clamp(0.0, 1.0) * self.velocity
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let oc = self.center - r.origin();
+        let center = self.center_at(r.time());
+        let oc = center - r.origin();
         let a = r.dir.norm_squared();
         let h = r.dir.dot(&oc);
         let c = oc.norm_squared() - self.radius * self.radius;
@@ -58,8 +84,9 @@ impl Hittable for Sphere {
 
         rec.t = root;
         rec.p = r.at(root);
-        rec.normal = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(r, rec.normal);
+        let outward_normal = (rec.p - center) / self.radius;
+        rec.set_face_normal(r, outward_normal);
+        (rec.u, rec.v) = sphere_uv(&outward_normal);
         rec.mat = self.mat.clone();
 
         true
@@ -68,4 +95,53 @@ impl Hittable for Sphere {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    // Directions are sampled uniformly inside the cone that the sphere covers as seen from
+    // the origin, or over every direction from inside the sphere.
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center_at(r.time()) - r.origin()).norm_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 1.0 / (4.0 * PI);
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        if solid_angle > 0.0 {
+            1.0 / solid_angle
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, origin: &Point3<f64>, time: f64, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let direction = self.center_at(time) - origin;
+        let distance_squared = direction.norm_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return sample_unit_vector(sampler.get_2d());
+        }
+
+        // Uniform direction in the cone of half-angle theta_max around the z axis.
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let (u, v) = sampler.get_2d();
+        let z = 1.0 + u * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * v;
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        let local = Vector3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+        Onb::new(&direction).transform(&local)
+    }
+}
+
+// Texture coordinates of a point on the unit sphere: u is the angle around the y axis
+// from x = -1, and v the angle from y = -1 to y = +1, both scaled to [0, 1].
+fn sphere_uv(p: &Vector3<f64>) -> (f64, f64) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
 }
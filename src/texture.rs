@@ -0,0 +1,286 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use na::Point3;
+
+use crate::{color::Color, framebuffer::Framebuffer, input, perlin::Perlin};
+
+// Color of a surface at a point, from the texture coordinates (u, v) of the hit and
+// its position p in space.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3<f64>) -> Color;
+}
+
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> SolidColor {
+        SolidColor { albedo }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3<f64>) -> Color {
+        self.albedo
+    }
+}
+
+// Checkerboard filling space with cubes of side `scale`, so it does not depend on how
+// the surface is parametrized.
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> CheckerTexture {
+        CheckerTexture {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> CheckerTexture {
+        CheckerTexture::new(
+            scale,
+            Arc::new(SolidColor::new(even)),
+            Arc::new(SolidColor::new(odd)),
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3<f64>) -> Color {
+        let sum: i64 = p
+            .iter()
+            .map(|&coord| (self.inv_scale * coord).floor() as i64)
+            .sum();
+
+        if sum % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+// Checkerboard in texture space, with `columns` squares along u and `rows` along v.
+pub struct UvCheckerTexture {
+    columns: f64,
+    rows: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl UvCheckerTexture {
+    pub fn new(
+        columns: usize,
+        rows: usize,
+        even: Arc<dyn Texture>,
+        odd: Arc<dyn Texture>,
+    ) -> UvCheckerTexture {
+        UvCheckerTexture {
+            columns: columns as f64,
+            rows: rows as f64,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(columns: usize, rows: usize, even: Color, odd: Color) -> UvCheckerTexture {
+        UvCheckerTexture::new(
+            columns,
+            rows,
+            Arc::new(SolidColor::new(even)),
+            Arc::new(SolidColor::new(odd)),
+        )
+    }
+}
+
+impl Texture for UvCheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3<f64>) -> Color {
+        let column = (u * self.columns).floor() as i64;
+        let row = (v * self.rows).floor() as i64;
+
+        if (column + row) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoisePattern {
+    Turbulence, // cloudy fractal noise
+    Marble,     // veins across the z axis, bent by turbulence
+    Wood,       // rings around the y axis, bent by turbulence
+}
+
+// Procedural texture blending from `low` to `high` following a Perlin noise pattern.
+// `scale` is the frequency of the pattern: larger values give finer details.
+pub struct NoiseTexture {
+    pattern: NoisePattern,
+    noise: Perlin,
+    scale: f64,
+    octaves: usize,
+    low: Arc<dyn Texture>,
+    high: Arc<dyn Texture>,
+}
+
+impl NoiseTexture {
+    // Black to white, with seed 0 and 7 octaves of turbulence.
+    pub fn new(pattern: NoisePattern, scale: f64) -> NoiseTexture {
+        NoiseTexture {
+            pattern,
+            noise: Perlin::new(0),
+            scale,
+            octaves: 7,
+            low: Arc::new(SolidColor::new(Color::new(0.0, 0.0, 0.0))),
+            high: Arc::new(SolidColor::new(Color::new(1.0, 1.0, 1.0))),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> NoiseTexture {
+        self.noise = Perlin::new(seed);
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: usize) -> NoiseTexture {
+        self.octaves = octaves.max(1);
+        self
+    }
+
+    pub fn with_textures(mut self, low: Arc<dyn Texture>, high: Arc<dyn Texture>) -> NoiseTexture {
+        self.low = low;
+        self.high = high;
+        self
+    }
+
+    pub fn with_colors(self, low: Color, high: Color) -> NoiseTexture {
+        self.with_textures(
+            Arc::new(SolidColor::new(low)),
+            Arc::new(SolidColor::new(high)),
+        )
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3<f64>) -> Color {
+        let q = p * self.scale;
+        let turbulence = self.noise.turbulence(&q, self.octaves);
+        let t = match self.pattern {
+            NoisePattern::Turbulence => turbulence.min(1.0),
+            NoisePattern::Marble => 0.5 * (1.0 + (q.z + 10.0 * turbulence).sin()),
+            NoisePattern::Wood => (q.x.hypot(q.z) + 0.5 * turbulence).fract(),
+        };
+        self.low.value(u, v, p).lerp(&self.high.value(u, v, p), t)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+}
+
+// How texture coordinates outside [0, 1] pick a texel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureWrap {
+    Repeat,
+    Clamp,  // stretches the border texels
+    Mirror, // repeats the image flipped every other time
+}
+
+impl TextureWrap {
+    // Maps any texel index onto 0..size.
+    fn texel(&self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+        let index = match self {
+            TextureWrap::Repeat => index.rem_euclid(size),
+            TextureWrap::Clamp => index.clamp(0, size - 1),
+            TextureWrap::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+        };
+        index as usize
+    }
+}
+
+// Image stretched over the (u, v) square, with (0, 0) at the bottom-left corner of the
+// image. Bilinear filtering and repeat wrapping by default.
+pub struct ImageTexture {
+    image: Framebuffer, // linear colors
+    filter: TextureFilter,
+    wrap: TextureWrap,
+}
+
+impl ImageTexture {
+    pub fn new(image: Framebuffer) -> ImageTexture {
+        ImageTexture {
+            image,
+            filter: TextureFilter::Bilinear,
+            wrap: TextureWrap::Repeat,
+        }
+    }
+
+    // Loads a PNG or PPM file, see input::load.
+    pub fn load(path: &Path) -> io::Result<ImageTexture> {
+        Ok(ImageTexture::new(input::load(path)?))
+    }
+
+    pub fn with_filter(mut self, filter: TextureFilter) -> ImageTexture {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: TextureWrap) -> ImageTexture {
+        self.wrap = wrap;
+        self
+    }
+
+    fn texel(&self, i: i64, j: i64) -> Color {
+        *self.image.get(
+            self.wrap.texel(i, self.image.width),
+            self.wrap.texel(j, self.image.height),
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3<f64>) -> Color {
+        // Solid cyan shows missing texture data.
+        if self.image.width == 0 || self.image.height == 0 {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        // Position in texels, image rows going from the top down.
+        let x = u * self.image.width as f64;
+        let y = (1.0 - v) * self.image.height as f64;
+
+        match self.filter {
+            TextureFilter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            TextureFilter::Bilinear => {
+                // Blend the four texels whose centers surround the point.
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (i, j) = (x.floor(), y.floor());
+                let (tx, ty) = (x - i, y - j);
+                let (i, j) = (i as i64, j as i64);
+
+                let top = self.texel(i, j).lerp(&self.texel(i + 1, j), tx);
+                let bottom = self.texel(i, j + 1).lerp(&self.texel(i + 1, j + 1), tx);
+                top.lerp(&bottom, ty)
+            }
+        }
+    }
+}
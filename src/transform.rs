@@ -0,0 +1,203 @@
+use std::sync::Arc;
+
+use na::{Affine3, Isometry3, Matrix4, Rotation3, Unit, Vector3};
+
+use crate::{
+    degrees_to_radians,
+    hittable::Hittable,
+    instance::{Instance, TransformError},
+};
+
+// An Instance built step by step: any mix of translation, rotation, scaling and shearing.
+// The steps only compose the object-to-world matrix; `into_instance` checks it and inverts
+// it once, into the Instance that does the work. A step that cannot be applied is kept as
+// an error and returned by `into_instance`, so the builders can still be chained.
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    object_to_world: Matrix4<f64>,
+    error: Option<TransformError>,
+}
+
+impl Transform {
+    pub fn new(object: Arc<dyn Hittable>, object_to_world: Affine3<f64>) -> Transform {
+        Transform {
+            object,
+            object_to_world: object_to_world.to_homogeneous(),
+            error: None,
+        }
+    }
+
+    pub fn identity(object: Arc<dyn Hittable>) -> Transform {
+        Transform::new(object, Affine3::identity())
+    }
+
+    pub fn from_isometry(object: Arc<dyn Hittable>, isometry: Isometry3<f64>) -> Transform {
+        Transform::new(object, Affine3::from_matrix_unchecked(isometry.to_homogeneous()))
+    }
+
+    // `matrix` must be affine, with (0, 0, 0, 1) as its last row.
+    pub fn from_matrix(object: Arc<dyn Hittable>, matrix: Matrix4<f64>) -> Transform {
+        let mut transform = Transform::new(object, Affine3::from_matrix_unchecked(matrix));
+        if matrix.row(3) != Matrix4::identity().row(3) {
+            transform.error = Some(TransformError::NotAffine);
+        }
+        transform
+    }
+
+    // The builders below apply one more step after the current transform.
+    pub fn translate(self, offset: Vector3<f64>) -> Transform {
+        self.then(Matrix4::new_translation(&offset))
+    }
+
+    // Rotates by `angle` degrees around `axis`, counterclockwise when the axis points
+    // toward the viewer. So do rotate_x, rotate_y and rotate_z.
+    pub fn rotate(mut self, axis: Vector3<f64>, angle: f64) -> Transform {
+        match Unit::try_new(axis, 0.0) {
+            Some(axis) => {
+                let rotation = Rotation3::from_axis_angle(&axis, degrees_to_radians(angle));
+                self.then(rotation.to_homogeneous())
+            }
+            None => {
+                self.error.get_or_insert(TransformError::ZeroRotationAxis);
+                self
+            }
+        }
+    }
+
+    pub fn rotate_x(self, angle: f64) -> Transform {
+        self.rotate(Vector3::x(), angle)
+    }
+
+    pub fn rotate_y(self, angle: f64) -> Transform {
+        self.rotate(Vector3::y(), angle)
+    }
+
+    pub fn rotate_z(self, angle: f64) -> Transform {
+        self.rotate(Vector3::z(), angle)
+    }
+
+    // Scales along each axis, around the origin of the current space.
+    pub fn scale(self, factors: Vector3<f64>) -> Transform {
+        self.then(Matrix4::new_nonuniform_scaling(&factors))
+    }
+
+    pub fn object_to_world(&self) -> &Matrix4<f64> {
+        &self.object_to_world
+    }
+
+    // Finishes the transform. Fails if a step was invalid or if the steps together do
+    // not make an invertible transform, as a zero scale does.
+    pub fn into_instance(self) -> Result<Instance, TransformError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Instance::new(self.object, self.object_to_world),
+        }
+    }
+
+    fn then(self, step: Matrix4<f64>) -> Transform {
+        Transform {
+            object: self.object,
+            object_to_world: step * self.object_to_world,
+            error: self.error,
+        }
+    }
+}
+
+impl TryFrom<Transform> for Instance {
+    type Error = TransformError;
+
+    fn try_from(transform: Transform) -> Result<Instance, TransformError> {
+        transform.into_instance()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::HitRecord, interval::Interval, material::DefaultMaterial, quad::Quad, ray::Ray,
+    };
+    use na::Point3;
+
+    fn square() -> Arc<dyn Hittable> {
+        Arc::new(Quad::new(
+            Point3::new(-1.0, -1.0, 0.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 2.0, 0.0),
+            Arc::new(DefaultMaterial::new()),
+        ))
+    }
+
+    fn apply(transform: Transform, v: Vector3<f64>) -> Vector3<f64> {
+        (transform.object_to_world() * v.push(0.0)).xyz()
+    }
+
+    #[test]
+    fn rotations_are_counterclockwise() {
+        let close = |a: Vector3<f64>, b: Vector3<f64>| (a - b).norm() < 1e-12;
+        let t = || Transform::identity(square());
+        assert!(close(apply(t().rotate_x(90.0), Vector3::y()), Vector3::z()));
+        assert!(close(apply(t().rotate_y(90.0), Vector3::z()), Vector3::x()));
+        assert!(close(apply(t().rotate_z(90.0), Vector3::x()), Vector3::y()));
+
+        for (axis, angle) in [
+            (Vector3::x(), 30.0),
+            (Vector3::y(), -45.0),
+            (Vector3::z(), 75.0),
+        ] {
+            let named = match axis {
+                a if a == Vector3::x() => t().rotate_x(angle),
+                a if a == Vector3::y() => t().rotate_y(angle),
+                _ => t().rotate_z(angle),
+            };
+            let general = t().rotate(axis, angle);
+            assert_eq!(named.object_to_world(), general.object_to_world());
+        }
+    }
+
+    #[test]
+    fn instances_hit_the_transformed_object() {
+        // The square in the xy plane, stood up in the xz plane and moved up by 5.
+        let instance = Transform::identity(square())
+            .scale(Vector3::new(2.0, 1.0, 1.0))
+            .rotate_x(90.0)
+            .translate(Vector3::new(0.0, 5.0, 0.0))
+            .into_instance()
+            .unwrap();
+
+        let r = Ray::new(Point3::new(1.5, 10.0, 0.5), Vector3::new(0.0, -1.0, 0.0));
+        let mut rec = HitRecord::default();
+        assert!(instance.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert!((rec.normal - Vector3::new(0.0, 1.0, 0.0)).norm() < 1e-9);
+
+        // Outside the stretched square.
+        let r = Ray::new(Point3::new(2.5, 10.0, 0.5), Vector3::new(0.0, -1.0, 0.0));
+        assert!(!instance.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+    }
+
+    #[test]
+    fn reports_invalid_transforms() {
+        let err = |t: Transform| t.into_instance().err();
+        let t = || Transform::identity(square());
+        assert_eq!(
+            err(t().rotate(Vector3::zeros(), 30.0).translate(Vector3::x())),
+            Some(TransformError::ZeroRotationAxis)
+        );
+        assert_eq!(
+            err(t().scale(Vector3::new(1.0, 0.0, 1.0))),
+            Some(TransformError::NotInvertible)
+        );
+        assert_eq!(
+            err(t().rotate_y(f64::NAN)),
+            Some(TransformError::NotInvertible)
+        );
+        let mut matrix = Matrix4::identity();
+        matrix[(3, 0)] = 1.0;
+        assert_eq!(
+            err(Transform::from_matrix(square(), matrix)),
+            Some(TransformError::NotAffine)
+        );
+        assert_eq!(err(t().rotate(Vector3::new(1.0, 1.0, 0.0), 30.0)), None);
+    }
+}
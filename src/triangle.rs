@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use na::{Point3, Vector3};
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+};
+
+pub struct Triangle {
+    vertices: [Point3<f64>; 3],
+    normals: Option<[Vector3<f64>; 3]>, // per-vertex shading normals
+    uvs: [(f64, f64); 3],
+    normal: Option<Vector3<f64>>, // geometric normal, following the vertex winding
+    mat: Arc<dyn Material>,
+    bbox: Aabb,
+}
+
+impl Triangle {
+    // A triangle without area, its vertices on one line, has no normal and is never hit.
+    pub fn new(a: Point3<f64>, b: Point3<f64>, c: Point3<f64>, mat: Arc<dyn Material>) -> Triangle {
+        Triangle {
+            vertices: [a, b, c],
+            normals: None,
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            normal: face_normal(&a, &b, &c),
+            mat,
+            bbox: triangle_bbox(&a, &b, &c),
+        }
+    }
+
+    // Shading normals, interpolated across the face instead of the flat geometric normal.
+    // Where they cancel out, the geometric normal is used.
+    pub fn with_normals(mut self, normals: [Vector3<f64>; 3]) -> Triangle {
+        self.normals = Some(normals);
+        self
+    }
+
+    // Texture coordinates of each vertex. By default the (u, v) of a hit are its barycentrics.
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Triangle {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn is_degenerate(&self) -> bool {
+        self.normal.is_none()
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let Some(normal) = self.normal else {
+            return false;
+        };
+        let [a, b, c] = &self.vertices;
+        let Some((t, bary)) = intersect(r, ray_t, a, b, c) else {
+            return false;
+        };
+
+        rec.t = t;
+        rec.p = Point3::from(bary[0] * a.coords + bary[1] * b.coords + bary[2] * c.coords);
+        rec.u = bary[0] * self.uvs[0].0 + bary[1] * self.uvs[1].0 + bary[2] * self.uvs[2].0;
+        rec.v = bary[0] * self.uvs[0].1 + bary[1] * self.uvs[1].1 + bary[2] * self.uvs[2].1;
+        rec.mat = self.mat.clone();
+        rec.set_face_normal(r, normal);
+        if let Some(n) = &self.normals {
+            set_shading_normal(rec, bary[0] * n[0] + bary[1] * n[1] + bary[2] * n[2]);
+        }
+        true
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+}
+
+pub(crate) fn triangle_bbox(a: &Point3<f64>, b: &Point3<f64>, c: &Point3<f64>) -> Aabb {
+    let min = Point3::new(
+        a.x.min(b.x).min(c.x),
+        a.y.min(b.y).min(c.y),
+        a.z.min(b.z).min(c.z),
+    );
+    let max = Point3::new(
+        a.x.max(b.x).max(c.x),
+        a.y.max(b.y).max(c.y),
+        a.z.max(b.z).max(c.z),
+    );
+    let mut bbox = Aabb::new();
+    bbox.aabb(&min, &max);
+    bbox
+}
+
+// Unit normal following the winding of a, b and c, or None when they are on one line.
+pub(crate) fn face_normal(
+    a: &Point3<f64>,
+    b: &Point3<f64>,
+    c: &Point3<f64>,
+) -> Option<Vector3<f64>> {
+    unit((b - a).cross(&(c - a)))
+}
+
+// Replaces the face normal of the hit with an interpolated shading normal, oriented on the
+// same side. A shading normal of zero length, where the vertex normals cancel out, keeps
+// the face normal.
+pub(crate) fn set_shading_normal(rec: &mut HitRecord, interpolated: Vector3<f64>) {
+    if let Some(n) = unit(interpolated) {
+        rec.normal = if rec.front_face { n } else { -n };
+    }
+}
+
+fn unit(v: Vector3<f64>) -> Option<Vector3<f64>> {
+    v.try_normalize(0.0)
+        .filter(|n| n.iter().all(|c| c.is_finite()))
+}
+
+// Watertight ray/triangle intersection (Woop, Benthin and Wald, 2013). Rays that pass
+// exactly through a shared edge or vertex hit one of the triangles, never none of them.
+// Returns the ray parameter and the barycentric weights of a, b and c.
+pub(crate) fn intersect(
+    r: &Ray,
+    ray_t: Interval,
+    a: &Point3<f64>,
+    b: &Point3<f64>,
+    c: &Point3<f64>,
+) -> Option<(f64, [f64; 3])> {
+    let dir = r.direction();
+
+    // Permute the axes so that z is the dominant direction, keeping the winding.
+    let kz = dir.iamax();
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if dir[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // Shear so that the ray points along +z.
+    let sx = dir[kx] / dir[kz];
+    let sy = dir[ky] / dir[kz];
+    let sz = 1.0 / dir[kz];
+
+    let a = a - r.origin();
+    let b = b - r.origin();
+    let c = c - r.origin();
+
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    // Scaled barycentrics, from the 2D edge functions.
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+
+    Some((t, [u / det, v / det, w / det]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DefaultMaterial;
+
+    fn triangle(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> Triangle {
+        Triangle::new(
+            Point3::from(a),
+            Point3::from(b),
+            Point3::from(c),
+            Arc::new(DefaultMaterial::new()),
+        )
+    }
+
+    fn hit(object: &Triangle, r: &Ray) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        object
+            .hit(r, Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    }
+
+    #[test]
+    fn hits_with_barycentric_uvs() {
+        let tri = triangle([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let r = Ray::new(Point3::new(0.25, 0.5, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let rec = hit(&tri, &r).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vector3::z());
+    }
+
+    #[test]
+    fn degenerate_triangles_are_never_hit() {
+        for tri in [
+            triangle([0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [2.0, 2.0, 0.0]),
+            triangle([1.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 1.0]),
+        ] {
+            assert!(tri.is_degenerate());
+            for dir in [Vector3::new(0.0, 0.0, -1.0), Vector3::new(1.0, 1.0, 0.0)] {
+                let r = Ray::new(Point3::new(0.0, 0.0, 1.0) - dir, dir);
+                assert!(hit(&tri, &r).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn cancelling_vertex_normals_keep_the_face_normal() {
+        let z = Vector3::z();
+        let tri = triangle([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]).with_normals([
+            z,
+            -z,
+            Vector3::zeros(),
+        ]);
+        // Halfway between a and b the interpolated normal is zero.
+        let r = Ray::new(Point3::new(0.5, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let rec = hit(&tri, &r).unwrap();
+        assert_eq!(rec.normal, z);
+
+        let r = Ray::new(Point3::new(0.1, 0.1, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let rec = hit(&tri, &r).unwrap();
+        assert!(rec.normal.iter().all(|c| c.is_finite()));
+        assert!((rec.normal.norm() - 1.0).abs() < 1e-12);
+    }
+}
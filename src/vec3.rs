@@ -1,11 +1,18 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
 use na::Vector3;
 
 use crate::{random_double, random_double_range};
 
+// The helpers below draw from the thread's random generator, which the camera's samplers
+// can't spread out. They are kept so existing code still builds; new code should take its
+// numbers from a Sampler and use sample_unit_vector and sample_unit_disk.
+#[deprecated(note = "draw the numbers from a Sampler instead")]
 pub fn random() -> Vector3<f64> {
     Vector3::new(random_double(), random_double(), random_double())
 }
 
+#[deprecated(note = "draw the numbers from a Sampler instead")]
 pub fn random_with_limits(min: f64, max: f64) -> Vector3<f64> {
     Vector3::new(
         random_double_range(min, max),
@@ -14,6 +21,8 @@ pub fn random_with_limits(min: f64, max: f64) -> Vector3<f64> {
     )
 }
 
+#[deprecated(note = "use sample_unit_vector with a point from a Sampler")]
+#[allow(deprecated)]
 pub fn random_in_unit_sphere() -> Vector3<f64> {
     loop {
         let p = random_with_limits(-1.0, 1.0);
@@ -23,10 +32,14 @@ pub fn random_in_unit_sphere() -> Vector3<f64> {
     }
 }
 
+#[deprecated(note = "use sample_unit_vector with a point from a Sampler")]
+#[allow(deprecated)]
 pub fn random_unit_vector() -> Vector3<f64> {
     random_in_unit_sphere().normalize()
 }
 
+#[deprecated(note = "use sample_unit_vector with a point from a Sampler")]
+#[allow(deprecated)]
 pub fn random_on_hemisphere(normal: Vector3<f64>) -> Vector3<f64> {
     let on_unit_sphere = random_unit_vector();
     if on_unit_sphere.dot(&normal) > 0.0 {
@@ -47,6 +60,7 @@ pub fn refract(uv: Vector3<f64>, n: Vector3<f64>, etai_over_etat: f64) -> Vector
     r_out_perp + r_out_parallel
 }
 
+#[deprecated(note = "use sample_unit_disk with a point from a Sampler")]
 pub fn random_in_unit_disk() -> Vector3<f64> {
     loop {
         let p = Vector3::new(
@@ -59,6 +73,31 @@ pub fn random_in_unit_disk() -> Vector3<f64> {
         }
     }
 }
+
+// Uniform direction from a uniform point of the unit square. Unlike a rejection loop, it
+// maps well stratified points to well stratified directions.
+pub fn sample_unit_vector((u, v): (f64, f64)) -> Vector3<f64> {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Uniform point of the unit disk from a uniform point of the unit square, with Shirley
+// and Chiu's concentric mapping, which keeps neighboring points close.
+pub fn sample_unit_disk((u, v): (f64, f64)) -> Vector3<f64> {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+    Vector3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
 pub trait Vector3Ext {
     fn near_zero(&self) -> bool;
 }